@@ -1,15 +1,33 @@
-use crate::{contracts, name::to_internal_name};
+use std::rc::Rc;
+
+use crate::{context::Context, contracts};
 
 const BASE_CAPACITY: usize = 10 * 1024;
+const MAX_TUPLE_LEN: usize = 10;
+const MAX_COLLECTION_LEN: usize = 4;
+const EMPTY_STRATEGY: &str = "proptest::strategy::LazyJust::new(Default::default)";
+
+const GUID_REGEX: &str = "[0-9a-f]{8}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{12}";
+const URI_REGEX: &str = "https://[a-z]{1,12}\\.example\\.com(/[a-z0-9]{1,8}){0,3}";
+const DATE_REGEX: &str = "(19[7-9][0-9]|20[0-9]{2})-(0[1-9]|1[0-2])-(0[1-9]|1[0-9]|2[0-8])";
+const TIME_REGEX: &str = "([01][0-9]|2[0-3]):[0-5][0-9]:[0-5][0-9]";
+const DATE_TIME_OFFSET_REGEX: &str = concat!(
+    "(19[7-9][0-9]|20[0-9]{2})-(0[1-9]|1[0-2])-(0[1-9]|1[0-9]|2[0-8])",
+    "T([01][0-9]|2[0-3]):[0-5][0-9]:[0-5][0-9]",
+    "(Z|[+-](0[0-9]|1[0-3]):[0-5][0-9])"
+);
+const TIME_SPAN_REGEX: &str = "([0-9]{1,3}\\.)?([01][0-9]|2[0-3]):[0-5][0-9]:[0-5][0-9]";
 
 pub(crate) struct CodeBuilder {
     data: String,
+    context: Rc<Context>,
 }
 
 impl CodeBuilder {
-    pub fn new() -> Self {
+    pub fn new(context: Rc<Context>) -> Self {
         Self {
             data: String::with_capacity(BASE_CAPACITY),
+            context,
         }
     }
 
@@ -48,8 +66,8 @@ impl CodeBuilder {
             UInt32 => "u32",
             Int64 => "i64",
             UInt64 => "u64",
-            Float32 => "float",
-            Float64 => "f32",
+            Float32 => "f32",
+            Float64 => "f64",
             DateOnly => "String",
             TimeOnly => "String",
             DateTimeOffset => "String",
@@ -81,6 +99,62 @@ impl CodeBuilder {
         self
     }
 
+    pub fn append_value_info(&mut self, value: &contracts::ValueRef) -> &mut Self {
+        use contracts::value_ref::Value::*;
+
+        self.data.push_str("cqrs_server::ValueInfo::");
+        match value.value.as_ref().unwrap() {
+            Null(_) => self.data.push_str("Null"),
+            Number(n) => self.data.push_str(&format!("Number({})", n.value)),
+            FloatingPoint(f) if f.value.is_nan() => self.data.push_str("FloatingPoint(f64::NAN)"),
+            FloatingPoint(f) if f.value.is_infinite() && f.value > 0.0 => {
+                self.data.push_str("FloatingPoint(f64::INFINITY)")
+            }
+            FloatingPoint(f) if f.value.is_infinite() => {
+                self.data.push_str("FloatingPoint(f64::NEG_INFINITY)")
+            }
+            FloatingPoint(f) => self.data.push_str(&format!("FloatingPoint({:?})", f.value)),
+            String(s) => self.data.push_str(&format!("String({:?})", s.value)),
+            Bool(b) => self.data.push_str(&format!("Bool({})", b.value)),
+        }
+
+        self
+    }
+
+    pub fn append_attribute_infos(&mut self, attributes: &[contracts::AttributeRef]) -> &mut Self {
+        use contracts::attribute_argument::Attribute::*;
+
+        self.data.push_str("&[");
+        for a in attributes.iter() {
+            self.append("cqrs_server::AttributeInfo { name: ")
+                .append_literal(&a.attribute_name)
+                .append(", arguments: &[");
+            for arg in a.argument.iter() {
+                match arg.attribute.as_ref().unwrap() {
+                    Positional(p) => self
+                        .append("cqrs_server::AttributeArgumentInfo::Positional { position: ")
+                        .append(&p.position.to_string())
+                        .append(", value: ")
+                        .append_value_info(p.value.as_ref().unwrap()),
+                    Named(n) => self
+                        .append("cqrs_server::AttributeArgumentInfo::Named { name: ")
+                        .append_literal(&n.name)
+                        .append(", value: ")
+                        .append_value_info(n.value.as_ref().unwrap()),
+                };
+                self.data.push_str(" }, ");
+            }
+            self.data.push_str("] }, ");
+        }
+        self.data.push(']');
+        self
+    }
+
+    pub fn append_literal(&mut self, value: &str) -> &mut Self {
+        self.data.push_str(&format!("{:?}", value));
+        self
+    }
+
     pub fn append_value_ref_type(&mut self, value: &contracts::ValueRef) -> &mut Self {
         use contracts::value_ref::Value::*;
         let t = match value.value.as_ref().unwrap() {
@@ -122,6 +196,19 @@ impl CodeBuilder {
         self
     }
 
+    pub fn append_boxed_type_ref(&mut self, type_ref: &contracts::TypeRef) -> &mut Self {
+        let inner = contracts::TypeRef {
+            nullable: false,
+            ..type_ref.clone()
+        };
+
+        if type_ref.nullable {
+            self.append("Option<Box<").append_type_ref(&inner).append(">>")
+        } else {
+            self.append("Box<").append_type_ref(&inner).append(">")
+        }
+    }
+
     pub fn append_generic_parameters(&mut self, args: &[contracts::GenericParameter]) -> &mut Self {
         if !args.is_empty() {
             self.data.push_str("<");
@@ -141,9 +228,32 @@ impl CodeBuilder {
         self
     }
 
+    pub fn append_bounded_generic_parameters(
+        &mut self,
+        args: &[contracts::GenericParameter],
+        bound: &str,
+    ) -> &mut Self {
+        if !args.is_empty() {
+            self.data.push('<');
+        }
+
+        for (i, t) in args.iter().enumerate() {
+            if i > 0 {
+                self.data.push_str(", ");
+            }
+            self.append(&t.name).append(": ").append(bound);
+        }
+
+        if !args.is_empty() {
+            self.data.push('>');
+        }
+
+        self
+    }
+
     pub fn append_internal_name(&mut self, name: &str) -> &mut Self {
-        self.data.push_str("crate::");
-        self.data.push_str(&to_internal_name(name));
+        let path = self.context.path_of(name);
+        self.data.push_str(&path);
         self
     }
 
@@ -165,4 +275,101 @@ impl CodeBuilder {
 
         self
     }
+
+    pub fn append_tuple<T>(
+        &mut self,
+        items: &[T],
+        each: &mut impl FnMut(&mut Self, &T),
+    ) -> &mut Self {
+        self.data.push('(');
+        if items.len() <= MAX_TUPLE_LEN {
+            for i in items.iter() {
+                each(self, i);
+                self.data.push_str(", ");
+            }
+        } else {
+            let chunk = items.len().div_ceil(MAX_TUPLE_LEN);
+            for c in items.chunks(chunk) {
+                self.append_tuple(c, each);
+                self.data.push_str(", ");
+            }
+        }
+        self.data.push(')');
+        self
+    }
+
+    pub fn append_strategy(
+        &mut self,
+        type_ref: &contracts::TypeRef,
+        recursive: &dyn Fn(&contracts::TypeRef) -> bool,
+    ) -> &mut Self {
+        use contracts::type_ref::Type::*;
+
+        if type_ref.nullable {
+            let inner = contracts::TypeRef {
+                nullable: false,
+                ..type_ref.clone()
+            };
+            if recursive(&inner) {
+                return self.append(EMPTY_STRATEGY);
+            }
+            return self
+                .append("proptest::option::of(")
+                .append_strategy(&inner, recursive)
+                .append(")");
+        }
+
+        match type_ref.r#type.as_ref().unwrap() {
+            Generic(g) => self.append("any::<").append(&g.name).append(">()"),
+            Internal(_) => self.append("any::<").append_type_ref(type_ref).append(">()"),
+            Known(k) => {
+                let kt = contracts::KnownType::from_i32(k.r#type).unwrap();
+                self.append_known_strategy(kt, &k.arguments, recursive)
+            }
+        }
+    }
+
+    fn append_known_strategy(
+        &mut self,
+        known: contracts::KnownType,
+        args: &[contracts::TypeRef],
+        recursive: &dyn Fn(&contracts::TypeRef) -> bool,
+    ) -> &mut Self {
+        use contracts::KnownType::*;
+
+        match known {
+            Guid => self.append_regex_strategy(GUID_REGEX),
+            Uri => self.append_regex_strategy(URI_REGEX),
+            DateOnly => self.append_regex_strategy(DATE_REGEX),
+            TimeOnly => self.append_regex_strategy(TIME_REGEX),
+            DateTimeOffset => self.append_regex_strategy(DATE_TIME_OFFSET_REGEX),
+            TimeSpan => self.append_regex_strategy(TIME_SPAN_REGEX),
+            Float32 => self.append("proptest::num::f32::NORMAL"),
+            Float64 => self.append("proptest::num::f64::NORMAL"),
+            Object => self.append("Just(serde_json::Value::Null)"),
+            Array if args.iter().any(recursive) => self.append(EMPTY_STRATEGY),
+            Array => self
+                .append("proptest::collection::vec(")
+                .append_strategy(&args[0], recursive)
+                .append(&format!(", 0..{})", MAX_COLLECTION_LEN)),
+            Map if args.iter().any(recursive) => self.append(EMPTY_STRATEGY),
+            Map => self
+                .append("proptest::collection::hash_map(")
+                .append_strategy(&args[0], recursive)
+                .append(", ")
+                .append_strategy(&args[1], recursive)
+                .append(&format!(", 0..{})", MAX_COLLECTION_LEN)),
+            _ => self
+                .append("any::<")
+                .append_known_type(known)
+                .append_generic_arguments(args)
+                .append(">()"),
+        }
+    }
+
+    fn append_regex_strategy(&mut self, regex: &str) -> &mut Self {
+        self.append("proptest::string::string_regex(")
+            .append(&format!("{:?}", regex))
+            .append(").unwrap()")
+    }
 }
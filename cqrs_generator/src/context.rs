@@ -0,0 +1,70 @@
+use std::collections::HashMap;
+
+use crate::{
+    contracts::{self, Export},
+    name::to_internal_name,
+    output::Generator,
+    recursion::Cycles,
+};
+
+pub(crate) struct Context {
+    pub arbitrary: bool,
+    pub module_root: String,
+    pub extern_namespaces: Vec<(String, String)>,
+    pub cycles: Cycles,
+    pub boxed: Cycles,
+    pub contracts: HashMap<String, usize>,
+}
+
+impl Context {
+    pub fn new(export: &Export, generator: &Generator) -> Self {
+        use contracts::statement::Content::*;
+
+        let mut extern_namespaces = generator.extern_namespaces.clone();
+        extern_namespaces.sort_by_key(|(ns, _)| std::cmp::Reverse(ns.len()));
+
+        let contracts = export
+            .statements
+            .iter()
+            .filter(|s| !is_extern(&extern_namespaces, &s.name))
+            .filter(|s| matches!(s.content, Some(Dto(_) | Enum(_) | Query(_) | Command(_))))
+            .enumerate()
+            .map(|(i, s)| (s.name.clone(), i))
+            .collect();
+
+        Self {
+            arbitrary: generator.arbitrary,
+            module_root: generator
+                .module_root
+                .clone()
+                .unwrap_or_else(|| "crate".to_string()),
+            extern_namespaces,
+            cycles: Cycles::new(export, true),
+            boxed: Cycles::new(export, false),
+            contracts,
+        }
+    }
+
+    pub fn is_extern(&self, name: &str) -> bool {
+        is_extern(&self.extern_namespaces, name)
+    }
+
+    pub fn path_of(&self, name: &str) -> String {
+        for (ns, path) in self.extern_namespaces.iter() {
+            if let Some(rest) = strip_namespace(name, ns) {
+                return format!("{}::{}", path, to_internal_name(rest));
+            }
+        }
+        format!("{}::{}", self.module_root, to_internal_name(name))
+    }
+}
+
+fn is_extern(extern_namespaces: &[(String, String)], name: &str) -> bool {
+    extern_namespaces
+        .iter()
+        .any(|(ns, _)| strip_namespace(name, ns).is_some())
+}
+
+fn strip_namespace<'a>(name: &'a str, namespace: &str) -> Option<&'a str> {
+    name.strip_prefix(namespace)?.strip_prefix('.')
+}
@@ -1,3 +1,5 @@
+use std::path::Path;
+
 use crate::{
     contracts::{Export, Statement},
     name::{FullName, split_fullname},
@@ -73,6 +75,41 @@ impl Hierarchy {
             }
         }
     }
+
+    pub fn write_split(self, builder: &mut StmtBuilder, dir: &Path) {
+        let Hierarchy::Namespace {
+            level, children, ..
+        } = self
+        else {
+            panic!("only a Namespace can be written as a module tree");
+        };
+
+        for c in children.into_iter() {
+            let Hierarchy::Namespace { name, .. } = &c else {
+                c.write_to(builder);
+                continue;
+            };
+
+            let module_dir = dir.join(name);
+            std::fs::create_dir_all(&module_dir).expect("cannot create the module directory");
+
+            // relative to the directory of the declaring file, also when the output is included
+            let module_file = if level == 0 {
+                let root = dir.file_name().expect("the output has a file name");
+                format!("{}/{}/mod.rs", root.to_string_lossy(), name)
+            } else {
+                format!("{}/mod.rs", name)
+            };
+            builder.declare(name, &module_file);
+
+            let mut module = builder.module();
+            module.prelude();
+            c.write_split(&mut module, &module_dir);
+
+            std::fs::write(module_dir.join("mod.rs"), module.build())
+                .expect("cannot write the module file");
+        }
+    }
 }
 
 impl From<Export> for Hierarchy {
@@ -85,11 +122,3 @@ impl From<Export> for Hierarchy {
         hierarchy
     }
 }
-
-impl Into<StmtBuilder> for Hierarchy {
-    fn into(self) -> StmtBuilder {
-        let mut builder = StmtBuilder::new();
-        self.write_to(&mut builder);
-        builder
-    }
-}
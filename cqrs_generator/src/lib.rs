@@ -1,8 +1,11 @@
 mod code_builder;
+mod context;
 mod contracts;
 mod hierarchy;
 mod name;
 mod output;
+mod recursion;
+mod schema;
 mod stmt_builder;
 
 pub use output::*;
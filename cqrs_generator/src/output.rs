@@ -1,37 +1,138 @@
-use std::path::{Path, PathBuf};
+use std::{
+    path::{Path, PathBuf},
+    rc::Rc,
+};
 
 use prost::Message;
 
-use crate::{contracts::Export, hierarchy::Hierarchy, stmt_builder::StmtBuilder};
+use crate::{
+    context::Context, contracts::Export, hierarchy::Hierarchy, schema::SchemaBuilder,
+    stmt_builder::StmtBuilder,
+};
 
-pub fn generate(input: impl AsRef<Path>) {
-    let mut out_dir: PathBuf = std::env::var("OUT_DIR")
-        .expect("this should be run in Cargo")
-        .try_into()
-        .unwrap();
-    let input = std::fs::read(input).expect("cannot read the input file");
-    let parsed_export = Export::decode(&input[..])
-        .expect("cannot decode the Export, the file is malformed, probably");
+#[derive(Clone, Debug, Default)]
+pub struct Generator {
+    split_namespaces: bool,
+    json_schemas: Option<PathBuf>,
+    pub(crate) arbitrary: bool,
+    pub(crate) module_root: Option<String>,
+    pub(crate) extern_namespaces: Vec<(String, String)>,
+}
+
+impl Generator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Writes every namespace to its own `mod.rs` in a directory next to the output file (named
+    /// after it, without the extension) and declares it with `pub mod`. The output file can be
+    /// `include!`d as before, or be a module file in the source tree itself.
+    pub fn split_namespaces(mut self) -> Self {
+        self.split_namespaces = true;
+        self
+    }
+
+    /// Additionally writes a JSON Schema (draft 2020-12) for every DTO, enum, query and command
+    /// to `dir`, one `<full name>.schema.json` file per contract.
+    pub fn json_schemas(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.json_schemas = Some(dir.into());
+        self
+    }
+
+    /// Emits `proptest::arbitrary::Arbitrary` impls for every generated type. The crate that
+    /// includes the output needs `proptest` as a dependency.
+    pub fn arbitrary(mut self) -> Self {
+        self.arbitrary = true;
+        self
+    }
+
+    /// Path of the module the output is `include!`d in, `crate` by default. Generated code refers
+    /// to other contracts (and the contract registry) through it, e.g. `crate::contracts`.
+    pub fn module_root(mut self, path: impl Into<String>) -> Self {
+        self.module_root = Some(path.into());
+        self
+    }
+
+    /// Treats contracts in `namespace` (and its children) as defined elsewhere, e.g. in a crate
+    /// that generates them itself. They are not emitted, and references to them go through `path`,
+    /// which stands for the namespace module itself (`shared_contracts::my_app::shared`).
+    pub fn extern_namespace(
+        mut self,
+        namespace: impl Into<String>,
+        path: impl Into<String>,
+    ) -> Self {
+        self.extern_namespaces.push((namespace.into(), path.into()));
+        self
+    }
+
+    pub fn generate(&self, input: impl AsRef<Path>) {
+        let mut out_dir: PathBuf = std::env::var("OUT_DIR")
+            .expect("this should be run in Cargo")
+            .into();
+        let parsed_export = read_export(input);
+
+        let mut filename = parsed_export.project_name.to_lowercase();
+        filename.push_str(".rs");
+
+        out_dir.push(filename);
+
+        self.write_to(parsed_export, out_dir);
+    }
 
-    let mut filename = parsed_export.project_name.to_lowercase();
-    filename.push_str(".rs");
+    pub fn generate_to(&self, input: impl AsRef<Path>, output: impl AsRef<Path>) {
+        self.write_to(read_export(input), output);
+    }
 
-    out_dir.push(filename);
+    pub fn write_to(&self, mut input: Export, output: impl AsRef<Path>) {
+        let output = output.as_ref();
+        if let Some(dir) = &self.json_schemas {
+            write_schemas(&input, dir);
+        }
 
-    write_to(parsed_export, out_dir);
+        let context = Context::new(&input, self);
+        input.statements.retain(|s| !context.is_extern(&s.name));
+
+        let mut builder = StmtBuilder::new(Rc::new(context));
+        builder.append_registry(&input.statements);
+
+        let hierarchy: Hierarchy = input.into();
+        if self.split_namespaces {
+            hierarchy.write_split(&mut builder, &output.with_extension(""));
+        } else {
+            hierarchy.write_to(&mut builder);
+        }
+
+        std::fs::write(output, builder.build()).expect("cannot write the output file");
+    }
+}
+
+pub fn generate(input: impl AsRef<Path>) {
+    Generator::new().generate(input);
 }
 
 pub fn generate_to(input: impl AsRef<Path>, output: impl AsRef<Path>) {
-    let input = std::fs::read(input).expect("cannot read the input file");
-    let parsed_export = Export::decode(&input[..])
-        .expect("cannot decode the Export, the file is malformed, probably");
-    write_to(parsed_export, output);
+    Generator::new().generate_to(input, output);
 }
 
 pub fn write_to(input: Export, output: impl AsRef<Path>) {
-    let hierarchy: Hierarchy = input.into();
-    let builder: StmtBuilder = hierarchy.into();
-    let contents = builder.build();
+    Generator::new().write_to(input, output);
+}
+
+fn write_schemas(input: &Export, dir: &Path) {
+    std::fs::create_dir_all(dir).expect("cannot create the schema directory");
 
-    std::fs::write(output, contents).expect("cannot write the output file");
+    let builder = SchemaBuilder::new(input);
+    for stmt in input.statements.iter() {
+        let Some(schema) = builder.build(stmt) else {
+            continue;
+        };
+        let contents = serde_json::to_string_pretty(&schema).expect("cannot serialize the schema");
+        std::fs::write(dir.join(format!("{}.schema.json", stmt.name)), contents)
+            .expect("cannot write the schema file");
+    }
+}
+
+fn read_export(input: impl AsRef<Path>) -> Export {
+    let input = std::fs::read(input).expect("cannot read the input file");
+    Export::decode(&input[..]).expect("cannot decode the Export, the file is malformed, probably")
 }
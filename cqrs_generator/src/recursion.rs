@@ -0,0 +1,138 @@
+use std::collections::HashMap;
+
+use crate::contracts::{self, Export};
+
+pub(crate) struct Cycles {
+    through_collections: bool,
+    components: HashMap<String, usize>,
+}
+
+struct Tarjan<'a> {
+    edges: &'a HashMap<&'a str, Vec<String>>,
+    index: HashMap<&'a str, usize>,
+    lowlink: HashMap<&'a str, usize>,
+    stack: Vec<&'a str>,
+    components: HashMap<String, usize>,
+    next_component: usize,
+}
+
+impl Cycles {
+    pub fn new(export: &Export, through_collections: bool) -> Self {
+        let edges: HashMap<&str, Vec<String>> = export
+            .statements
+            .iter()
+            .map(|s| {
+                let mut out = vec![];
+                if let Some(descr) = type_descriptor(s) {
+                    for t in descr.extends.iter() {
+                        collect(t, through_collections, &mut out);
+                    }
+                    for p in descr.properties.iter() {
+                        collect(p.r#type.as_ref().unwrap(), through_collections, &mut out);
+                    }
+                }
+                (s.name.as_str(), out)
+            })
+            .collect();
+
+        let mut tarjan = Tarjan {
+            edges: &edges,
+            index: HashMap::new(),
+            lowlink: HashMap::new(),
+            stack: vec![],
+            components: HashMap::new(),
+            next_component: 0,
+        };
+        for node in edges.keys() {
+            if !tarjan.index.contains_key(node) {
+                tarjan.visit(node);
+            }
+        }
+
+        Self {
+            through_collections,
+            components: tarjan.components,
+        }
+    }
+
+    pub fn mentions(&self, current: &str, type_ref: &contracts::TypeRef) -> bool {
+        let mut names = vec![];
+        collect(type_ref, self.through_collections, &mut names);
+        names.iter().any(|n| self.same_cycle(current, n))
+    }
+
+    fn same_cycle(&self, a: &str, b: &str) -> bool {
+        matches!(
+            (self.components.get(a), self.components.get(b)),
+            (Some(x), Some(y)) if x == y
+        )
+    }
+}
+
+impl<'a> Tarjan<'a> {
+    fn visit(&mut self, node: &'a str) {
+        let idx = self.index.len();
+        self.index.insert(node, idx);
+        self.lowlink.insert(node, idx);
+        self.stack.push(node);
+
+        let edges = self.edges;
+        for next in edges[node].iter() {
+            let Some((&next, _)) = edges.get_key_value(next.as_str()) else {
+                continue;
+            };
+            if !self.index.contains_key(next) {
+                self.visit(next);
+                let low = self.lowlink[node].min(self.lowlink[next]);
+                self.lowlink.insert(node, low);
+            } else if self.stack.contains(&next) {
+                let low = self.lowlink[node].min(self.index[next]);
+                self.lowlink.insert(node, low);
+            }
+        }
+
+        if self.lowlink[node] == self.index[node] {
+            loop {
+                let member = self.stack.pop().unwrap();
+                self.components
+                    .insert(member.to_string(), self.next_component);
+                if member == node {
+                    break;
+                }
+            }
+            self.next_component += 1;
+        }
+    }
+}
+
+fn type_descriptor(stmt: &contracts::Statement) -> Option<&contracts::TypeDescriptor> {
+    use contracts::statement::Content::*;
+
+    match stmt.content.as_ref()? {
+        Dto(dto) => dto.type_descriptor.as_ref(),
+        Query(query) => query.type_descriptor.as_ref(),
+        Command(command) => command.type_descriptor.as_ref(),
+        _ => None,
+    }
+}
+
+fn collect(type_ref: &contracts::TypeRef, through_collections: bool, out: &mut Vec<String>) {
+    use contracts::type_ref::Type::*;
+
+    match type_ref.r#type.as_ref().unwrap() {
+        Generic(_) => {}
+        Internal(i) => {
+            out.push(i.name.clone());
+            for a in i.arguments.iter() {
+                collect(a, through_collections, out);
+            }
+        }
+        Known(k) => {
+            if through_collections {
+                for a in k.arguments.iter() {
+                    collect(a, through_collections, out);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,219 @@
+use std::collections::{BTreeMap, HashMap};
+
+use serde_json::{json, Map, Value};
+
+use crate::contracts::{self, Export, Statement};
+
+const DRAFT: &str = "https://json-schema.org/draft/2020-12/schema";
+
+pub(crate) struct SchemaBuilder<'a> {
+    statements: HashMap<&'a str, &'a Statement>,
+}
+
+#[derive(Default)]
+struct Definitions {
+    defs: BTreeMap<String, Value>,
+    pending: Vec<String>,
+}
+
+impl<'a> SchemaBuilder<'a> {
+    pub fn new(export: &'a Export) -> Self {
+        let statements = export
+            .statements
+            .iter()
+            .map(|s| (s.name.as_str(), s))
+            .collect();
+        Self { statements }
+    }
+
+    pub fn build(&self, stmt: &Statement) -> Option<Value> {
+        let mut defs = Definitions::default();
+        let root = self.definition(stmt, &mut defs)?;
+        defs.defs.insert(stmt.name.clone(), root);
+
+        while let Some(name) = defs.pending.pop() {
+            if defs.defs.contains_key(&name) {
+                continue;
+            }
+            let definition = self
+                .statements
+                .get(name.as_str())
+                .and_then(|s| self.definition(s, &mut defs))
+                .unwrap_or_else(|| json!({}));
+            defs.defs.insert(name, definition);
+        }
+
+        Some(json!({
+            "$schema": DRAFT,
+            "$id": stmt.name,
+            "title": stmt.name,
+            "$ref": def_ref(&stmt.name),
+            "$defs": defs.defs,
+        }))
+    }
+
+    fn definition(&self, stmt: &Statement, defs: &mut Definitions) -> Option<Value> {
+        use contracts::statement::Content::*;
+
+        let mut schema = match stmt.content.as_ref().unwrap() {
+            Enum(r#enum) => enum_schema(r#enum),
+            Dto(dto) => self.object_schema(dto.type_descriptor.as_ref().unwrap(), defs),
+            Query(query) => self.object_schema(query.type_descriptor.as_ref().unwrap(), defs),
+            Command(command) => {
+                self.object_schema(command.type_descriptor.as_ref().unwrap(), defs)
+            }
+            _ => return None,
+        };
+
+        if !stmt.comment.is_empty() {
+            schema["description"] = json!(stmt.comment);
+        }
+
+        Some(schema)
+    }
+
+    fn object_schema(&self, descr: &contracts::TypeDescriptor, defs: &mut Definitions) -> Value {
+        let mut properties = Map::new();
+        let mut required = vec![];
+
+        for p in descr.properties.iter() {
+            let type_ref = p.r#type.as_ref().unwrap();
+            let mut schema = self.type_ref(type_ref, defs);
+            if !p.comment.is_empty() {
+                schema = json!({ "allOf": [schema], "description": p.comment });
+            }
+            if !type_ref.nullable {
+                required.push(p.name.clone());
+            }
+            properties.insert(p.name.clone(), schema);
+        }
+
+        let object = json!({
+            "type": "object",
+            "properties": properties,
+            "required": required,
+        });
+
+        if descr.extends.is_empty() {
+            object
+        } else {
+            let mut all_of: Vec<_> = descr
+                .extends
+                .iter()
+                .map(|e| self.type_ref(e, defs))
+                .collect();
+            all_of.push(object);
+            json!({ "allOf": all_of })
+        }
+    }
+
+    fn type_ref(&self, type_ref: &contracts::TypeRef, defs: &mut Definitions) -> Value {
+        use contracts::type_ref::Type::*;
+
+        let schema = match type_ref.r#type.as_ref().unwrap() {
+            Generic(_) => json!({}),
+            Internal(i) => {
+                defs.pending.push(i.name.clone());
+                json!({ "$ref": def_ref(&i.name) })
+            }
+            Known(k) => {
+                let kt = contracts::KnownType::from_i32(k.r#type).unwrap();
+                self.known_type(kt, &k.arguments, defs)
+            }
+        };
+
+        if type_ref.nullable {
+            json!({ "anyOf": [schema, { "type": "null" }] })
+        } else {
+            schema
+        }
+    }
+
+    fn known_type(
+        &self,
+        known: contracts::KnownType,
+        args: &[contracts::TypeRef],
+        defs: &mut Definitions,
+    ) -> Value {
+        use contracts::KnownType::*;
+
+        match known {
+            String => json!({ "type": "string" }),
+            Boolean => json!({ "type": "boolean" }),
+            UInt8 => integer(u8::MIN as i64, u8::MAX as u64),
+            Int8 => integer(i8::MIN as i64, i8::MAX as u64),
+            Int16 => integer(i16::MIN as i64, i16::MAX as u64),
+            UInt16 => integer(u16::MIN as i64, u16::MAX as u64),
+            Int32 => integer(i32::MIN as i64, i32::MAX as u64),
+            UInt32 => integer(u32::MIN as i64, u32::MAX as u64),
+            Int64 => integer(i64::MIN, i64::MAX as u64),
+            UInt64 => integer(u64::MIN as i64, u64::MAX),
+            Float32 | Float64 => json!({ "type": "number" }),
+            Guid | Uri | DateOnly | TimeOnly | DateTimeOffset | TimeSpan => {
+                let name = known.as_str_name();
+                defs.defs
+                    .entry(name.to_string())
+                    .or_insert_with(|| known_definition(known));
+                json!({ "$ref": def_ref(name) })
+            }
+            Array => {
+                let items = args
+                    .first()
+                    .map(|a| self.type_ref(a, defs))
+                    .unwrap_or_else(|| json!({}));
+                json!({ "type": "array", "items": items })
+            }
+            Map => {
+                let values = args
+                    .get(1)
+                    .map(|a| self.type_ref(a, defs))
+                    .unwrap_or_else(|| json!({}));
+                json!({ "type": "object", "additionalProperties": values })
+            }
+            _ => json!({}),
+        }
+    }
+}
+
+fn enum_schema(r#enum: &contracts::statement::Enum) -> Value {
+    let members: Vec<_> = r#enum
+        .members
+        .iter()
+        .map(|m| {
+            let mut member = json!({ "const": m.value, "title": m.name });
+            if !m.comment.is_empty() {
+                member["description"] = json!(m.comment);
+            }
+            member
+        })
+        .collect();
+    json!({ "type": "integer", "oneOf": members })
+}
+
+fn known_definition(known: contracts::KnownType) -> Value {
+    use contracts::KnownType::*;
+
+    match known {
+        Guid => json!({ "type": "string", "format": "uuid" }),
+        Uri => json!({ "type": "string", "format": "uri-reference" }),
+        DateOnly => json!({ "type": "string", "format": "date" }),
+        TimeOnly => json!({
+            "type": "string",
+            "pattern": "^\\d{2}:\\d{2}:\\d{2}(\\.\\d{1,7})?$",
+        }),
+        DateTimeOffset => json!({ "type": "string", "format": "date-time" }),
+        TimeSpan => json!({
+            "type": "string",
+            "pattern": "^-?(\\d+\\.)?\\d{2}:\\d{2}:\\d{2}(\\.\\d{1,7})?$",
+        }),
+        _ => json!({}),
+    }
+}
+
+fn integer(minimum: i64, maximum: u64) -> Value {
+    json!({ "type": "integer", "minimum": minimum, "maximum": maximum })
+}
+
+fn def_ref(name: &str) -> String {
+    format!("#/$defs/{}", name)
+}
@@ -1,5 +1,8 @@
+use std::rc::Rc;
+
 use crate::{
     code_builder::CodeBuilder,
+    context::Context,
     contracts,
     name::{get_type, to_const, to_field, to_namespace, to_type},
 };
@@ -7,12 +10,21 @@ use crate::{
 pub struct StmtBuilder {
     builder: CodeBuilder,
     indent: usize,
+    context: Rc<Context>,
 }
 
 impl StmtBuilder {
-    pub fn new() -> Self {
-        let builder = CodeBuilder::new();
-        Self { builder, indent: 0 }
+    pub fn new(context: Rc<Context>) -> Self {
+        let builder = CodeBuilder::new(context.clone());
+        Self {
+            builder,
+            indent: 0,
+            context,
+        }
+    }
+
+    pub fn module(&self) -> Self {
+        Self::new(self.context.clone())
     }
 
     pub fn descend(&mut self, namespace: &str) {
@@ -23,6 +35,26 @@ impl StmtBuilder {
             .append(" {")
             .finish();
         self.indent();
+        self.prelude();
+    }
+
+    pub fn declare(&mut self, namespace: &str, file: &str) {
+        self.line()
+            .append("#[allow(unused_imports, dead_code)]")
+            .finish();
+        self.line()
+            .append("#[path = ")
+            .append(&format!("{:?}", file))
+            .append("]")
+            .finish();
+        self.line()
+            .append("pub mod ")
+            .append(namespace)
+            .append(";")
+            .finish();
+    }
+
+    pub fn prelude(&mut self) {
         self.line()
             .append("use serde::{Serialize, Deserialize};")
             .finish();
@@ -95,6 +127,11 @@ impl StmtBuilder {
 
         self.dedent();
         self.line().append("}").finish();
+
+        if self.context.arbitrary {
+            let members: Vec<_> = r#enum.members.iter().map(|m| m.name.as_str()).collect();
+            self.append_arbitrary_enum(&to_type(get_type(&stmt.name)), &members);
+        }
     }
 
     pub fn append_dto(&mut self, stmt: &contracts::Statement, dto: &contracts::statement::Dto) {
@@ -138,6 +175,8 @@ impl StmtBuilder {
         self.dedent();
         self.line().append("}").finish();
 
+        self.append_info_fn(stmt);
+
         self.dedent();
         self.line().append("}").finish();
     }
@@ -165,17 +204,32 @@ impl StmtBuilder {
             .finish();
         self.indent();
 
+        let mut codes = vec![];
         for e in command.error_codes.iter() {
-            self.append_codes("", e.code.as_ref().unwrap());
+            collect_codes("", e.code.as_ref().unwrap(), &mut codes);
+        }
+
+        if codes.is_empty() {
+            codes.push(("__MarkerForEmptyErrorCodes".to_string(), 0));
         }
 
-        if command.error_codes.is_empty() {
-            self.line().append("__MarkerForEmptyErrorCodes = 0,").finish();
+        for (code_name, code) in codes.iter() {
+            self.line()
+                .append(code_name)
+                .append(" = ")
+                .append(&code.to_string())
+                .append(",")
+                .finish();
         }
 
         self.dedent();
         self.line().append("}").finish();
 
+        if self.context.arbitrary {
+            let members: Vec<_> = codes.iter().map(|(n, _)| n.as_str()).collect();
+            self.append_arbitrary_enum(&format!("{}ErrorCodes", to_type(name)), &members);
+        }
+
         self.line()
             .append("impl")
             .append_generic_parameters(&descr.generic_parameters)
@@ -204,6 +258,8 @@ impl StmtBuilder {
         self.dedent();
         self.line().append("}").finish();
 
+        self.append_info_fn(stmt);
+
         self.dedent();
         self.line().append("}").finish();
     }
@@ -252,25 +308,33 @@ impl StmtBuilder {
             };
 
             let type_name = get_type(&internal.name);
+            let boxed = self.context.boxed.mentions(&stmt.name, p);
 
-            self.line()
-                .append("pub ")
-                .append(&to_field(type_name))
-                .append(": ")
-                .append_internal_name(&internal.name)
-                .append_generic_arguments(&internal.arguments)
-                .append(", ")
-                .finish();
+            let line = self.line();
+            line.append("pub ").append(&to_field(type_name)).append(": ");
+            if boxed {
+                line.append("Box<");
+            }
+            line.append_internal_name(&internal.name)
+                .append_generic_arguments(&internal.arguments);
+            if boxed {
+                line.append(">");
+            }
+            line.append(", ").finish();
         }
 
         for p in descr.properties.iter() {
-            self.line()
-                .append("pub ")
-                .append(&to_field(&p.name))
-                .append(": ")
-                .append_type_ref(p.r#type.as_ref().unwrap())
-                .append(", ")
-                .finish();
+            let type_ref = p.r#type.as_ref().unwrap();
+            let boxed = self.context.boxed.mentions(&stmt.name, type_ref);
+
+            let line = self.line();
+            line.append("pub ").append(&to_field(&p.name)).append(": ");
+            if boxed {
+                line.append_boxed_type_ref(type_ref);
+            } else {
+                line.append_type_ref(type_ref);
+            }
+            line.append(", ").finish();
         }
 
         for g in descr.generic_parameters.iter() {
@@ -286,24 +350,348 @@ impl StmtBuilder {
         self.dedent();
 
         self.line().append("}").finish();
+
+        if self.context.arbitrary {
+            self.append_arbitrary_struct(descr, stmt);
+        }
+    }
+
+    fn append_info_fn(&mut self, stmt: &contracts::Statement) {
+        let Some(index) = self.context.contracts.get(&stmt.name).copied() else {
+            return;
+        };
+
+        let context = self.context.clone();
+
+        self.line().finish();
+        self.line()
+            .append("fn info() -> Option<&'static cqrs_server::ContractInfo> {")
+            .finish();
+        self.indent();
+        self.line()
+            .append("Some(&")
+            .append(&context.module_root)
+            .append("::CONTRACTS[")
+            .append(&index.to_string())
+            .append("])")
+            .finish();
+        self.dedent();
+        self.line().append("}").finish();
     }
 
-    fn append_codes(&mut self, prefix: &str, code: &contracts::error_code::Code) {
-        match code {
-            contracts::error_code::Code::Single(s) => {
+    fn append_arbitrary_struct(
+        &mut self,
+        descr: &contracts::TypeDescriptor,
+        stmt: &contracts::Statement,
+    ) {
+        let context = self.context.clone();
+        let recursive = |t: &contracts::TypeRef| context.cycles.mentions(&stmt.name, t);
+
+        let mut fields = vec![];
+        for p in descr.extends.iter() {
+            if let Some(contracts::type_ref::Type::Internal(internal)) = p.r#type.as_ref() {
+                fields.push((to_field(get_type(&internal.name)), p));
+            }
+        }
+        for p in descr.properties.iter() {
+            fields.push((to_field(&p.name), p.r#type.as_ref().unwrap()));
+        }
+
+        self.line()
+            .append("impl")
+            .append_bounded_generic_parameters(
+                &descr.generic_parameters,
+                "proptest::arbitrary::Arbitrary + 'static",
+            )
+            .append(" proptest::arbitrary::Arbitrary for ")
+            .append(&to_type(get_type(&stmt.name)))
+            .append_generic_parameters(&descr.generic_parameters)
+            .append(" {")
+            .finish();
+        self.indent();
+
+        self.line().append("type Parameters = ();").finish();
+        self.line()
+            .append("type Strategy = proptest::strategy::BoxedStrategy<Self>;")
+            .finish();
+        self.line().finish();
+        self.line()
+            .append("fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {")
+            .finish();
+        self.indent();
+
+        self.line().append("use proptest::prelude::*;").finish();
+        if fields.is_empty() {
+            self.line()
+                .append("proptest::strategy::LazyJust::new(|| Self {")
+                .finish();
+        } else {
+            self.line()
+                .append_tuple(&fields, &mut |b, (_, t)| {
+                    b.append_strategy(t, &recursive);
+                })
+                .finish();
+            self.indent();
+            self.line()
+                .append(".prop_map(|")
+                .append_tuple(&fields, &mut |b, (n, _)| {
+                    b.append(n);
+                })
+                .append("| Self {")
+                .finish();
+        }
+        self.indent();
+
+        for (n, t) in fields.iter() {
+            if !t.nullable && context.boxed.mentions(&stmt.name, t) {
                 self.line()
-                    .append(prefix)
-                    .append(&s.name)
-                    .append(" = ")
-                    .append(&s.code.to_string())
-                    .append(",")
+                    .append(n)
+                    .append(": Box::new(")
+                    .append(n)
+                    .append("),")
                     .finish();
+            } else {
+                self.line().append(n).append(",").finish();
+            }
+        }
+        for g in descr.generic_parameters.iter() {
+            self.line()
+                .append(&to_field(&g.name))
+                .append(": std::marker::PhantomData,")
+                .finish();
+        }
+
+        self.dedent();
+        self.line().append("})").finish();
+        self.line().append(".boxed()").finish();
+        if !fields.is_empty() {
+            self.dedent();
+        }
+
+        self.dedent();
+        self.line().append("}").finish();
+
+        self.dedent();
+        self.line().append("}").finish();
+    }
+
+    fn append_arbitrary_enum(&mut self, name: &str, members: &[&str]) {
+        if members.is_empty() {
+            return;
+        }
+
+        self.line()
+            .append("impl proptest::arbitrary::Arbitrary for ")
+            .append(name)
+            .append(" {")
+            .finish();
+        self.indent();
+
+        self.line().append("type Parameters = ();").finish();
+        self.line()
+            .append("type Strategy = proptest::sample::Select<Self>;")
+            .finish();
+        self.line().finish();
+        self.line()
+            .append("fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {")
+            .finish();
+        self.indent();
+
+        self.line().append("proptest::sample::select(vec![").finish();
+        self.indent();
+        for m in members.iter() {
+            self.line().append("Self::").append(m).append(",").finish();
+        }
+        self.dedent();
+        self.line().append("])").finish();
+
+        self.dedent();
+        self.line().append("}").finish();
+
+        self.dedent();
+        self.line().append("}").finish();
+    }
+}
+
+impl StmtBuilder {
+    pub fn append_registry(&mut self, statements: &[contracts::Statement]) {
+        self.line()
+            .append("pub static CONTRACTS: &[cqrs_server::ContractInfo] = &[")
+            .finish();
+        self.indent();
+
+        for stmt in statements.iter() {
+            if self.context.contracts.contains_key(&stmt.name) {
+                self.append_contract_info(stmt);
             }
-            contracts::error_code::Code::Group(g) => {
-                let prefix = format!("{}{}", prefix, g.name);
-                for c in g.inner_codes.iter() {
-                    self.append_codes(&prefix, c.code.as_ref().unwrap());
+        }
+
+        self.dedent();
+        self.line().append("];").finish();
+    }
+
+    fn append_contract_info(&mut self, stmt: &contracts::Statement) {
+        use contracts::statement::Content::*;
+
+        let mut codes = vec![];
+        let (kind, descr, result_type, members) = match stmt.content.as_ref().unwrap() {
+            Dto(dto) => ("Dto", dto.type_descriptor.as_ref(), None, &[][..]),
+            Enum(r#enum) => ("Enum", None, None, &r#enum.members[..]),
+            Query(query) => (
+                "Query",
+                query.type_descriptor.as_ref(),
+                query.return_type.as_ref(),
+                &[][..],
+            ),
+            Command(command) => {
+                for e in command.error_codes.iter() {
+                    collect_codes("", e.code.as_ref().unwrap(), &mut codes);
                 }
+                ("Command", command.type_descriptor.as_ref(), None, &[][..])
+            }
+            _ => return,
+        };
+
+        self.line().append("cqrs_server::ContractInfo {").finish();
+        self.indent();
+
+        self.line()
+            .append("name: ")
+            .append_literal(&stmt.name)
+            .append(",")
+            .finish();
+        self.line()
+            .append("kind: cqrs_server::ContractKind::")
+            .append(kind)
+            .append(",")
+            .finish();
+        self.line()
+            .append("comment: ")
+            .append_literal(&stmt.comment)
+            .append(",")
+            .finish();
+        self.line()
+            .append("attributes: ")
+            .append_attribute_infos(&stmt.attributes)
+            .append(",")
+            .finish();
+
+        self.line().append("generic_parameters: &[").finish();
+        self.indent();
+        for g in descr.iter().flat_map(|d| d.generic_parameters.iter()) {
+            self.line().append_literal(&g.name).append(",").finish();
+        }
+        self.dedent();
+        self.line().append("],").finish();
+
+        self.line().append("extends: &[").finish();
+        self.indent();
+        for e in descr.iter().flat_map(|d| d.extends.iter()) {
+            self.line()
+                .append_literal(&describe_type(e))
+                .append(",")
+                .finish();
+        }
+        self.dedent();
+        self.line().append("],").finish();
+
+        self.line().append("properties: &[").finish();
+        self.indent();
+        for p in descr.iter().flat_map(|d| d.properties.iter()) {
+            self.line()
+                .append("cqrs_server::PropertyInfo { name: ")
+                .append_literal(&p.name)
+                .append(", type_name: ")
+                .append_literal(&describe_type(p.r#type.as_ref().unwrap()))
+                .append(", comment: ")
+                .append_literal(&p.comment)
+                .append(", attributes: ")
+                .append_attribute_infos(&p.attributes)
+                .append(" },")
+                .finish();
+        }
+        self.dedent();
+        self.line().append("],").finish();
+
+        self.line().append("members: &[").finish();
+        self.indent();
+        for m in members.iter() {
+            self.line()
+                .append("cqrs_server::EnumMemberInfo { name: ")
+                .append_literal(&m.name)
+                .append(", value: ")
+                .append(&m.value.to_string())
+                .append(", comment: ")
+                .append_literal(&m.comment)
+                .append(" },")
+                .finish();
+        }
+        self.dedent();
+        self.line().append("],").finish();
+
+        self.line().append("error_codes: &[").finish();
+        self.indent();
+        for (code_name, code) in codes.iter() {
+            self.line()
+                .append("cqrs_server::ErrorCodeInfo { name: ")
+                .append_literal(code_name)
+                .append(", code: ")
+                .append(&code.to_string())
+                .append(" },")
+                .finish();
+        }
+        self.dedent();
+        self.line().append("],").finish();
+
+        match result_type {
+            Some(r) => self
+                .line()
+                .append("result_type: Some(")
+                .append_literal(&describe_type(r))
+                .append("),")
+                .finish(),
+            None => self.line().append("result_type: None,").finish(),
+        }
+
+        self.dedent();
+        self.line().append("},").finish();
+    }
+}
+
+fn describe_type(type_ref: &contracts::TypeRef) -> String {
+    use contracts::type_ref::Type::*;
+
+    let (mut name, args) = match type_ref.r#type.as_ref().unwrap() {
+        Generic(g) => (g.name.clone(), &[][..]),
+        Internal(i) => (i.name.clone(), &i.arguments[..]),
+        Known(k) => {
+            let kt = contracts::KnownType::from_i32(k.r#type).unwrap();
+            (kt.as_str_name().to_string(), &k.arguments[..])
+        }
+    };
+
+    if !args.is_empty() {
+        let args: Vec<_> = args.iter().map(describe_type).collect();
+        name.push('<');
+        name.push_str(&args.join(", "));
+        name.push('>');
+    }
+    if type_ref.nullable {
+        name.push('?');
+    }
+
+    name
+}
+
+fn collect_codes(prefix: &str, code: &contracts::error_code::Code, out: &mut Vec<(String, i32)>) {
+    match code {
+        contracts::error_code::Code::Single(s) => {
+            out.push((format!("{}{}", prefix, s.name), s.code));
+        }
+        contracts::error_code::Code::Group(g) => {
+            let prefix = format!("{}{}", prefix, g.name);
+            for c in g.inner_codes.iter() {
+                collect_codes(&prefix, c.code.as_ref().unwrap(), out);
             }
         }
     }
@@ -0,0 +1,119 @@
+use error_stack::Report;
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    contracts::*,
+    event_store::{EventStore, EventStoreError},
+    outbox::Outbox,
+    problem::InternalError,
+};
+
+/// State rebuilt from the events of its stream, `{TYPE}-{id}`.
+pub trait Aggregate: Default + Send {
+    const TYPE: &'static str;
+
+    type Event: Serialize + DeserializeOwned + Send;
+
+    fn apply(&mut self, event: &Self::Event);
+
+    fn stream(id: &str) -> String {
+        format!("{}-{}", Self::TYPE, id)
+    }
+}
+
+/// Decides which events a command produces, or why it is rejected.
+pub trait Handles<C>: Aggregate
+where
+    C: Command,
+{
+    fn handle(&self, command: &C) -> Result<Vec<Self::Event>, ValidationErrors<C>>;
+}
+
+/// An aggregate with the version of its stream it was rebuilt from.
+pub struct Loaded<A> {
+    pub aggregate: A,
+    pub version: u64,
+}
+
+pub async fn load<A, S>(store: &S, id: &str) -> Result<Loaded<A>, EventStoreError>
+where
+    A: Aggregate,
+    S: EventStore + ?Sized,
+{
+    let mut aggregate = A::default();
+    let mut version = 0;
+    for stored in store.load(&A::stream(id), 0).await? {
+        let event = serde_json::from_value(stored.data).map_err(EventStoreError::Serialization)?;
+        aggregate.apply(&event);
+        version = stored.version;
+    }
+    Ok(Loaded { aggregate, version })
+}
+
+/// Loads the aggregate, lets it handle `command` and appends the events it produced.
+///
+/// When the stream was appended to in the meantime, the command fails with `conflict`, so the
+/// client can retry it. Failures of the store are internal errors.
+pub async fn execute<A, C, S>(
+    store: &S,
+    id: &str,
+    command: &C,
+    conflict: C::ErrorCodes,
+) -> Result<CommandResult<C>, InternalError>
+where
+    A: Handles<C>,
+    C: Command + Serialize,
+    S: EventStore + ?Sized,
+{
+    execute_with_outbox::<A, C, S>(store, id, command, conflict, &Outbox::new()).await
+}
+
+/// Like `execute`, and enqueues the messages of `outbox` with the events, in the same transaction.
+/// The messages are discarded when the command fails.
+pub async fn execute_with_outbox<A, C, S>(
+    store: &S,
+    id: &str,
+    command: &C,
+    conflict: C::ErrorCodes,
+    outbox: &Outbox,
+) -> Result<CommandResult<C>, InternalError>
+where
+    A: Handles<C>,
+    C: Command + Serialize,
+    S: EventStore + ?Sized,
+{
+    let Loaded { aggregate, version } = load::<A, S>(store, id).await.map_err(internal)?;
+
+    let events = match aggregate.handle(command) {
+        Ok(events) => events,
+        Err(errors) => {
+            outbox.take();
+            return Ok(errors.into());
+        }
+    };
+    let messages = outbox.take();
+    if events.is_empty() && messages.is_empty() {
+        return Ok(CommandResult::success());
+    }
+
+    let events = events
+        .iter()
+        .map(serde_json::to_value)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| internal(EventStoreError::Serialization(e)))?;
+
+    let appended = store
+        .append_with_outbox(A::TYPE, &A::stream(id), version, events, messages)
+        .await;
+    match appended {
+        Ok(_) => Ok(CommandResult::success()),
+        Err(EventStoreError::Conflict { .. }) => Ok(CommandResult::single_error(
+            ValidationError::new("", "The data was modified in the meantime.", conflict),
+        )),
+        Err(e) => Err(internal(e)),
+    }
+}
+
+fn internal(error: EventStoreError) -> InternalError {
+    Report::new(error).into()
+}
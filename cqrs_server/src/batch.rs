@@ -0,0 +1,282 @@
+use std::{
+    convert::Infallible,
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use axum::{
+    body::{Bytes, HttpBody},
+    extract::FromRequest,
+    http::{header, HeaderMap, HeaderValue, Method, Request, StatusCode},
+    response::{IntoResponse, Response},
+    BoxError, Router,
+};
+use futures_util::{stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use tower::{Layer, Service, ServiceExt};
+
+use crate::{
+    contracts::Query, format::Format, input::CQRSInput, problem::ProblemDetails,
+    registry::ContractKind, routing::RouteNaming,
+};
+
+/// A query of a batch, e.g. `{"Name": "App.Sites.ListSites", "Payload": {}}`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "PascalCase")]
+pub struct BatchQuery {
+    pub name: String,
+    pub payload: serde_json::Value,
+}
+
+/// The outcome of a query of a batch: its status and either its result or its error body.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "PascalCase")]
+pub struct BatchQueryResult {
+    pub status: u16,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<serde_json::Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<serde_json::Value>,
+}
+
+/// Serves queries in batches on `route`: a list of `BatchQuery` is answered with a
+/// `BatchQueryResult` per query, in order. Only the given queries are executed, others are not
+/// found.
+///
+/// Every query is sent on its own through the wrapped router, with the headers of the batch, so
+/// wrap the router after adding the layers a query needs, e.g. authorization, `Extension(Metrics)`
+/// and `Extension(ContractTracing)`. Layers added afterwards run once for the whole batch and the
+/// extensions they insert do not reach the queries.
+///
+/// Use `wrap` rather than `Router::layer`, which layers every route on its own.
+///
+/// A batch of more than `max_queries` queries is rejected with 413, and at most `max_concurrency`
+/// of its queries run at the same time.
+#[derive(Clone, Debug)]
+pub struct BatchQueries {
+    route: String,
+    naming: RouteNaming,
+    queries: Vec<&'static str>,
+    max_queries: usize,
+    max_concurrency: usize,
+}
+
+const DEFAULT_MAX_QUERIES: usize = 50;
+const DEFAULT_MAX_CONCURRENCY: usize = 8;
+
+impl BatchQueries {
+    pub fn new(route: impl Into<String>) -> Self {
+        Self {
+            route: route.into(),
+            naming: RouteNaming::default(),
+            queries: vec![],
+            max_queries: DEFAULT_MAX_QUERIES,
+            max_concurrency: DEFAULT_MAX_CONCURRENCY,
+        }
+    }
+
+    pub fn naming(mut self, naming: RouteNaming) -> Self {
+        self.naming = naming;
+        self
+    }
+
+    pub fn query<Q: Query>(self) -> Self {
+        self.named(Q::name())
+    }
+
+    /// The most queries a batch may have, 50 by default.
+    pub fn max_queries(mut self, max_queries: usize) -> Self {
+        self.max_queries = max_queries;
+        self
+    }
+
+    /// The most queries of a batch that run at the same time, 8 by default.
+    pub fn max_concurrency(mut self, max_concurrency: usize) -> Self {
+        self.max_concurrency = max_concurrency.max(1);
+        self
+    }
+
+    pub fn wrap<B>(self, router: Router<(), B>) -> Router<(), B>
+    where
+        B: HttpBody + From<Bytes> + Send + 'static,
+        B::Data: Send,
+        B::Error: Into<BoxError>,
+    {
+        Router::new().fallback_service(self.layer(router))
+    }
+
+    pub(crate) fn named(mut self, name: &'static str) -> Self {
+        if !self.queries.contains(&name) {
+            self.queries.push(name);
+        }
+        self
+    }
+}
+
+impl<S> Layer<S> for BatchQueries {
+    type Service = BatchQueriesService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        BatchQueriesService {
+            inner,
+            config: Arc::new(self.clone()),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct BatchQueriesService<S> {
+    inner: S,
+    config: Arc<BatchQueries>,
+}
+
+impl<S, B> Service<Request<B>> for BatchQueriesService<S>
+where
+    S: Service<Request<B>, Response = Response, Error = Infallible> + Clone + Send + 'static,
+    S::Future: Send,
+    B: HttpBody + From<Bytes> + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+{
+    type Response = Response;
+    type Error = Infallible;
+    type Future = Pin<Box<dyn Future<Output = Result<Response, Infallible>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<B>) -> Self::Future {
+        if request.method() != Method::POST || request.uri().path() != self.config.route {
+            return Box::pin(self.inner.call(request));
+        }
+
+        let inner = self.inner.clone();
+        let config = self.config.clone();
+        Box::pin(async move { Ok(batch(inner, &config, request).await) })
+    }
+}
+
+async fn batch<S, B>(inner: S, config: &BatchQueries, request: Request<B>) -> Response
+where
+    S: Service<Request<B>, Response = Response, Error = Infallible> + Clone,
+    B: HttpBody + From<Bytes> + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+{
+    let headers = request.headers().clone();
+    let batch = match CQRSInput::<Vec<BatchQuery>>::from_request(request, &()).await {
+        Ok(CQRSInput(batch)) => batch,
+        Err(rejection) => return rejection.into_response(),
+    };
+
+    if batch.len() > config.max_queries {
+        return ProblemDetails::<()>::new(StatusCode::PAYLOAD_TOO_LARGE)
+            .with_detail(format!(
+                "A batch has at most {} queries, this one has {}",
+                config.max_queries,
+                batch.len()
+            ))
+            .into_response();
+    }
+
+    let headers = &headers;
+    let results: Vec<_> = stream::iter(batch)
+        .map(move |query| execute(inner.clone(), config, headers, query))
+        .buffered(config.max_concurrency)
+        .collect()
+        .await;
+
+    let format = Format::from_accept(headers);
+    match format.encode(&results) {
+        Ok(body) => (
+            [
+                (header::CONTENT_TYPE, format.content_type()),
+                (header::VARY, "accept"),
+            ],
+            body,
+        )
+            .into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e).into_response(),
+    }
+}
+
+async fn execute<S, B>(
+    inner: S,
+    config: &BatchQueries,
+    headers: &HeaderMap,
+    query: BatchQuery,
+) -> BatchQueryResult
+where
+    S: Service<Request<B>, Response = Response, Error = Infallible>,
+    B: HttpBody + From<Bytes> + Send + 'static,
+{
+    let Some(name) = config.queries.iter().find(|q| **q == query.name) else {
+        return failed(
+            StatusCode::NOT_FOUND,
+            format!("query `{}` is not registered", query.name),
+        );
+    };
+    let Some(route) = config.naming.route(ContractKind::Query, name) else {
+        return failed(
+            StatusCode::NOT_FOUND,
+            format!("query `{}` has no route", name),
+        );
+    };
+
+    let body = serde_json::to_vec(&query.payload).unwrap_or_default();
+    let mut request = match Request::post(route).body(B::from(Bytes::from(body))) {
+        Ok(request) => request,
+        Err(e) => return failed(StatusCode::BAD_REQUEST, e.to_string()),
+    };
+
+    *request.headers_mut() = headers.clone();
+    request.headers_mut().remove(header::CONTENT_LENGTH);
+    request.headers_mut().insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/json"),
+    );
+    request
+        .headers_mut()
+        .insert(header::ACCEPT, HeaderValue::from_static("application/json"));
+
+    let response = match inner.oneshot(request).await {
+        Ok(response) => response,
+        Err(e) => match e {},
+    };
+    let status = response.status();
+    let body = match hyper::body::to_bytes(response.into_body()).await {
+        Ok(body) => body,
+        Err(e) => return failed(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    };
+
+    let body = serde_json::from_slice(&body)
+        .unwrap_or_else(|_| String::from_utf8_lossy(&body).into_owned().into());
+    let body = Some(body).filter(|b| b != "");
+
+    if status.is_success() {
+        BatchQueryResult {
+            status: status.as_u16(),
+            result: body,
+            error: None,
+        }
+    } else {
+        BatchQueryResult {
+            status: status.as_u16(),
+            result: None,
+            error: body,
+        }
+    }
+}
+
+fn failed(status: StatusCode, detail: String) -> BatchQueryResult {
+    let problem = ProblemDetails::<()>::new(status).with_detail(detail);
+
+    BatchQueryResult {
+        status: problem.status,
+        result: None,
+        error: serde_json::to_value(problem).ok(),
+    }
+}
@@ -0,0 +1,366 @@
+use std::{
+    collections::HashMap,
+    io,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    async_trait,
+    body::{Bytes, HttpBody},
+    http::{header, Request, StatusCode},
+    middleware::{from_fn, Next},
+    response::{IntoResponse, Response},
+    routing::MethodRouter,
+    BoxError,
+};
+use error_stack::Report;
+
+use crate::{
+    contracts::{Command, FailedCommand, Query},
+    format::Format,
+    idempotency::{buffer, fits_buffer, unreadable, StoredResponse},
+    problem::InternalError,
+};
+
+/// Stores the results of queries by query name and serialized input. Expired results behave as
+/// if they were never stored.
+#[async_trait]
+pub trait QueryCacheStore: Send + Sync {
+    async fn get(&self, query: &str, input: &str) -> io::Result<Option<StoredResponse>>;
+
+    async fn put(
+        &self,
+        query: &str,
+        input: &str,
+        response: StoredResponse,
+        ttl: Duration,
+    ) -> io::Result<()>;
+
+    /// Drops the results of `query` for every input.
+    async fn invalidate(&self, query: &str) -> io::Result<()>;
+}
+
+/// Serves queries registered with `CQRSBuilder::cached_query` from a cache, until their TTL
+/// expires or a command invalidates them. Only successful results are cached, by the name of the
+/// query and its input, so they must not depend on who sends the query. Inputs and results
+/// larger than `MAX_BUFFERED_BODY`, or of unknown length, bypass the cache.
+///
+/// Enable it with `router.layer(Extension(QueryCache::default()))`, which keeps results in
+/// memory.
+#[derive(Clone)]
+pub struct QueryCache {
+    store: Arc<dyn QueryCacheStore>,
+    /// Queries invalidated by each command.
+    invalidations: Arc<HashMap<&'static str, Vec<&'static str>>>,
+    /// Bumped on every invalidation of a query, so that results computed before it are not
+    /// stored after it.
+    generations: Arc<Mutex<HashMap<&'static str, u64>>>,
+}
+
+impl QueryCache {
+    pub fn new(store: impl QueryCacheStore + 'static) -> Self {
+        Self {
+            store: Arc::new(store),
+            invalidations: Default::default(),
+            generations: Default::default(),
+        }
+    }
+
+    /// Drops the cached results of `Q` whenever a `C` command succeeds.
+    pub fn invalidates<C: Command, Q: Query>(mut self) -> Self {
+        let queries = Arc::make_mut(&mut self.invalidations)
+            .entry(C::name())
+            .or_default();
+        if !queries.contains(&Q::name()) {
+            queries.push(Q::name());
+        }
+        self
+    }
+
+    /// Drops the cached results of `query` for every input.
+    pub async fn invalidate(&self, query: &'static str) -> io::Result<()> {
+        *self.generations.lock().unwrap().entry(query).or_default() += 1;
+        self.store.invalidate(query).await
+    }
+
+    fn generation(&self, query: &str) -> u64 {
+        let generations = self.generations.lock().unwrap();
+        generations.get(query).copied().unwrap_or_default()
+    }
+}
+
+impl Default for QueryCache {
+    fn default() -> Self {
+        Self::new(InMemoryQueryCacheStore::new())
+    }
+}
+
+/// How often the in-memory store drops expired results of every query. Results are checked for
+/// expiry when read, so this only bounds the memory held by results that are never read again.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+struct CachedResults {
+    /// Results by query and input, with their expiry.
+    results: HashMap<String, HashMap<String, (StoredResponse, Instant)>>,
+    len: usize,
+    swept_at: Instant,
+}
+
+impl CachedResults {
+    fn sweep(&mut self, now: Instant) {
+        for inputs in self.results.values_mut() {
+            inputs.retain(|_, (_, expires_at)| *expires_at > now);
+        }
+        self.results.retain(|_, inputs| !inputs.is_empty());
+        self.len = self.results.values().map(HashMap::len).sum();
+        self.swept_at = now;
+    }
+
+    /// Drops the `count` results that expire first.
+    fn evict(&mut self, count: usize) {
+        let mut entries: Vec<_> = self
+            .results
+            .iter()
+            .flat_map(|(query, inputs)| {
+                inputs.iter().map(move |(input, (_, expires_at))| {
+                    (*expires_at, query.clone(), input.clone())
+                })
+            })
+            .collect();
+        entries.sort_unstable();
+        for (_, query, input) in entries.into_iter().take(count) {
+            self.remove(&query, &input);
+        }
+    }
+
+    fn remove(&mut self, query: &str, input: &str) {
+        let Some(inputs) = self.results.get_mut(query) else {
+            return;
+        };
+        if inputs.remove(input).is_some() {
+            self.len -= 1;
+        }
+        if inputs.is_empty() {
+            self.results.remove(query);
+        }
+    }
+}
+
+/// Keeps at most `max_entries` results, 10 000 by default. When it is full, the results that
+/// expire first are dropped.
+#[derive(Clone)]
+pub struct InMemoryQueryCacheStore {
+    results: Arc<Mutex<CachedResults>>,
+    max_entries: usize,
+}
+
+impl InMemoryQueryCacheStore {
+    pub fn new() -> Self {
+        Self {
+            results: Arc::new(Mutex::new(CachedResults {
+                results: HashMap::new(),
+                len: 0,
+                swept_at: Instant::now(),
+            })),
+            max_entries: 10_000,
+        }
+    }
+
+    pub fn max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = max_entries.max(1);
+        self
+    }
+}
+
+impl Default for InMemoryQueryCacheStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl QueryCacheStore for InMemoryQueryCacheStore {
+    async fn get(&self, query: &str, input: &str) -> io::Result<Option<StoredResponse>> {
+        let now = Instant::now();
+        let mut results = self.results.lock().unwrap();
+        let cached = results
+            .results
+            .get(query)
+            .and_then(|inputs| inputs.get(input));
+        match cached {
+            Some((response, expires_at)) if *expires_at > now => Ok(Some(response.clone())),
+            Some(_) => {
+                results.remove(query, input);
+                Ok(None)
+            }
+            None => Ok(None),
+        }
+    }
+
+    async fn put(
+        &self,
+        query: &str,
+        input: &str,
+        response: StoredResponse,
+        ttl: Duration,
+    ) -> io::Result<()> {
+        let now = Instant::now();
+        let mut results = self.results.lock().unwrap();
+        results.remove(query, input);
+        if results.len >= self.max_entries || now.duration_since(results.swept_at) >= SWEEP_INTERVAL
+        {
+            results.sweep(now);
+        }
+        if results.len >= self.max_entries {
+            // make room for a tenth of the entries, so that a full cache is not swept on every put
+            let count = results.len + 1 - self.max_entries * 9 / 10;
+            results.evict(count);
+        }
+
+        results
+            .results
+            .entry(query.to_string())
+            .or_default()
+            .insert(input.to_string(), (response, now + ttl));
+        results.len += 1;
+        Ok(())
+    }
+
+    async fn invalidate(&self, query: &str) -> io::Result<()> {
+        let mut results = self.results.lock().unwrap();
+        if let Some(inputs) = results.results.remove(query) {
+            results.len -= inputs.len();
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn cached<S, B>(
+    query: &'static str,
+    ttl: Duration,
+    method_router: MethodRouter<S, B>,
+) -> MethodRouter<S, B>
+where
+    S: Clone + Send + Sync + 'static,
+    B: HttpBody + From<Bytes> + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+{
+    method_router.layer(from_fn(move |request, next| {
+        serve_from_cache(query, ttl, request, next)
+    }))
+}
+
+async fn serve_from_cache<B>(
+    query: &'static str,
+    ttl: Duration,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response
+where
+    B: HttpBody + From<Bytes>,
+    B::Error: Into<BoxError>,
+{
+    let Some(cache) = request.extensions().get::<QueryCache>().cloned() else {
+        return next.run(request).await;
+    };
+
+    let (parts, body) = request.into_parts();
+    if !fits_buffer(&body) {
+        return next.run(Request::from_parts(parts, body)).await;
+    }
+    let body = match buffer(body).await {
+        Ok(body) => body,
+        Err(e) => return unreadable(e),
+    };
+
+    // the same input in another format or with other whitespace is the same entry, but results
+    // are kept apart by the format they are encoded in
+    let encoded_in = Format::from_accept(&parts.headers);
+    let input = Format::from_content_type(&parts.headers)
+        .and_then(|format| format.decode::<serde_json::Value>(&body).ok())
+        .map(|input| format!("{} {}", encoded_in.content_type(), input));
+    let request = Request::from_parts(parts, B::from(body));
+    let Some(input) = input else {
+        return next.run(request).await;
+    };
+
+    match cache.store.get(query, &input).await {
+        Ok(Some(response)) => return response.into_response(),
+        Ok(None) => {}
+        Err(e) => tracing::warn!("cannot read the cached results of `{}`: {}", query, e),
+    }
+
+    let generation = cache.generation(query);
+    let response = next.run(request).await;
+    if response.status() != StatusCode::OK || !fits_buffer(response.body()) {
+        return response;
+    }
+
+    let (parts, body) = response.into_parts();
+    let body = match buffer(body).await {
+        Ok(body) => body,
+        Err(e) => return InternalError::from(Report::new(io::Error::other(e))).into_response(),
+    };
+
+    if cache.generation(query) == generation {
+        let stored = StoredResponse {
+            status: parts.status.as_u16(),
+            content_type: parts
+                .headers
+                .get(header::CONTENT_TYPE)
+                .and_then(|v| v.to_str().ok())
+                .map(String::from),
+            body: body.to_vec(),
+        };
+        if let Err(e) = cache.store.put(query, &input, stored, ttl).await {
+            tracing::warn!("cannot cache the result of `{}`: {}", query, e);
+        }
+    }
+
+    (parts, body).into_response()
+}
+
+pub(crate) fn invalidating<S, B>(
+    command: &'static str,
+    method_router: MethodRouter<S, B>,
+) -> MethodRouter<S, B>
+where
+    S: Clone + Send + Sync + 'static,
+    B: HttpBody + Send + 'static,
+{
+    method_router.layer(from_fn(move |request, next| {
+        invalidate_queries(command, request, next)
+    }))
+}
+
+async fn invalidate_queries<B>(
+    command: &'static str,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let cache = request.extensions().get::<QueryCache>().cloned();
+    let response = next.run(request).await;
+
+    let Some(cache) = cache else {
+        return response;
+    };
+    let failed =
+        !response.status().is_success() || response.extensions().get::<FailedCommand>().is_some();
+    if failed {
+        return response;
+    }
+
+    for query in cache.invalidations.get(command).into_iter().flatten() {
+        if let Err(e) = cache.invalidate(query).await {
+            tracing::error!(
+                "cannot invalidate the cached results of `{}` after `{}`: {}",
+                query,
+                command,
+                e
+            );
+        }
+    }
+    response
+}
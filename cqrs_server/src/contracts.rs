@@ -1,20 +1,29 @@
-use std::marker::PhantomData;
+use axum::{http::StatusCode, response::IntoResponse};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
-use axum::{
-    response::{IntoResponse, Response},
-    Json,
+use crate::{
+    format::{respond, Format},
+    problem::ProblemDetails,
+    registry::ContractInfo,
 };
-use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
 pub trait Command {
     type ErrorCodes: DeserializeOwned + Serialize + std::fmt::Debug;
 
     fn name() -> &'static str;
+
+    fn info() -> Option<&'static ContractInfo> {
+        None
+    }
 }
 
 pub trait Query {
     type Result: Serialize + DeserializeOwned;
     fn name() -> &'static str;
+
+    fn info() -> Option<&'static ContractInfo> {
+        None
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -28,6 +37,13 @@ where
     pub error_code: T::ErrorCodes,
 }
 
+/// Errors of a failed command, usable with `?` on stable: handlers can return
+/// `Result<CommandResult<C>, ValidationErrors<C>>`.
+#[derive(Debug)]
+pub struct ValidationErrors<T>(pub Vec<ValidationError<T>>)
+where
+    T: Command;
+
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all = "PascalCase")]
 pub struct CommandResult<T>
@@ -37,16 +53,77 @@ where
     pub validation_errors: Vec<ValidationError<T>>,
 }
 
-pub struct QueryResult<T>(Response, PhantomData<T>)
+/// Added to the response of a command that failed validation, so that middleware can tell it from
+/// a success.
+#[derive(Clone, Debug)]
+pub struct FailedCommand {
+    pub error_codes: Vec<serde_json::Value>,
+}
+
+/// The outcome of a query. Failures are sent as `application/problem+json`: `NotFound` as 404,
+/// `Forbidden` as 403 and `Failed` as 422, with the domain error in the `error` member.
+pub enum QueryResult<T, E = ()>
 where
-    T: Query;
+    T: Query,
+{
+    Ok(T::Result),
+    NotFound,
+    Forbidden,
+    Failed(E),
+}
 
-impl<T> QueryResult<T>
+impl<T, E> QueryResult<T, E>
 where
     T: Query,
 {
-    pub fn new(data: &T::Result) -> Self {
-        Self(Json(data).into_response(), PhantomData)
+    pub fn ok(data: T::Result) -> Self {
+        Self::Ok(data)
+    }
+
+    pub fn new(data: &T::Result) -> Self
+    where
+        T::Result: Clone,
+    {
+        Self::ok(data.clone())
+    }
+
+    pub fn not_found() -> Self {
+        Self::NotFound
+    }
+
+    pub fn forbidden() -> Self {
+        Self::Forbidden
+    }
+
+    pub fn failed(error: E) -> Self {
+        Self::Failed(error)
+    }
+
+    pub fn is_ok(&self) -> bool {
+        matches!(self, Self::Ok(_))
+    }
+
+    pub fn data(&self) -> Option<&T::Result> {
+        match self {
+            Self::Ok(data) => Some(data),
+            _ => None,
+        }
+    }
+
+    pub fn into_data(self) -> Option<T::Result> {
+        match self {
+            Self::Ok(data) => Some(data),
+            _ => None,
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::Ok(_) => StatusCode::OK,
+            Self::NotFound => StatusCode::NOT_FOUND,
+            Self::Forbidden => StatusCode::FORBIDDEN,
+            Self::Failed(_) => StatusCode::UNPROCESSABLE_ENTITY,
+        }
     }
 }
 
@@ -97,6 +174,78 @@ where
     pub fn was_successful(&self) -> bool {
         self.validation_errors.is_empty()
     }
+
+    pub fn into_result(self) -> Result<(), ValidationErrors<T>> {
+        if self.validation_errors.is_empty() {
+            Ok(())
+        } else {
+            Err(ValidationErrors(self.validation_errors))
+        }
+    }
+}
+
+impl<T> From<ValidationError<T>> for ValidationErrors<T>
+where
+    T: Command,
+{
+    fn from(value: ValidationError<T>) -> Self {
+        Self(vec![value])
+    }
+}
+
+impl<T> From<Vec<ValidationError<T>>> for ValidationErrors<T>
+where
+    T: Command,
+{
+    fn from(value: Vec<ValidationError<T>>) -> Self {
+        Self(value)
+    }
+}
+
+impl<T> From<ValidationErrors<T>> for CommandResult<T>
+where
+    T: Command + Serialize,
+{
+    fn from(value: ValidationErrors<T>) -> Self {
+        Self::fail(value.0)
+    }
+}
+
+impl<T> From<Result<(), ValidationErrors<T>>> for CommandResult<T>
+where
+    T: Command + Serialize,
+{
+    fn from(value: Result<(), ValidationErrors<T>>) -> Self {
+        match value {
+            Ok(()) => Self::success(),
+            Err(errors) => errors.into(),
+        }
+    }
+}
+
+pub trait IntoCommandResult<T>
+where
+    T: Command + Serialize,
+{
+    fn into_command_result(self) -> CommandResult<T>;
+}
+
+impl<T> IntoCommandResult<T> for CommandResult<T>
+where
+    T: Command + Serialize,
+{
+    fn into_command_result(self) -> CommandResult<T> {
+        self
+    }
+}
+
+impl<T> IntoCommandResult<T> for Result<CommandResult<T>, ValidationErrors<T>>
+where
+    T: Command + Serialize,
+{
+    fn into_command_result(self) -> CommandResult<T> {
+        self.unwrap_or_else(Into::into)
+    }
 }
 
 impl<T> IntoResponse for CommandResult<T>
@@ -104,19 +253,49 @@ where
     T: Command + Serialize,
 {
     fn into_response(self) -> axum::response::Response {
-        Json(self).into_response()
+        let failed = (!self.was_successful()).then(|| FailedCommand {
+            error_codes: self
+                .validation_errors
+                .iter()
+                .map(|e| serde_json::to_value(&e.error_code).unwrap_or_default())
+                .collect(),
+        });
+
+        let mut response = respond(StatusCode::OK, &self, Format::Json.content_type());
+        if let Some(failed) = failed {
+            response.extensions_mut().insert(failed);
+        }
+        response
     }
 }
 
-impl<T> IntoResponse for QueryResult<T>
+impl<T> IntoResponse for ValidationErrors<T>
+where
+    T: Command + Serialize,
+{
+    fn into_response(self) -> axum::response::Response {
+        CommandResult::from(self).into_response()
+    }
+}
+
+impl<T, E> IntoResponse for QueryResult<T, E>
 where
     T: Query,
+    E: Serialize,
 {
     fn into_response(self) -> axum::response::Response {
-        self.0
+        let status = self.status();
+        match self {
+            Self::Ok(data) => respond(status, &data, Format::Json.content_type()),
+            Self::NotFound | Self::Forbidden => ProblemDetails::<()>::new(status).into_response(),
+            Self::Failed(error) => ProblemDetails::new(status)
+                .with_error(error)
+                .into_response(),
+        }
     }
 }
 
+#[cfg(feature = "nightly")]
 impl<T> std::ops::Try for CommandResult<T>
 where
     T: Command + Serialize,
@@ -137,6 +316,15 @@ where
     }
 }
 
+#[cfg(feature = "nightly")]
+impl<T> std::ops::Residual<()> for CommandResult<T>
+where
+    T: Command + Serialize,
+{
+    type TryType = CommandResult<T>;
+}
+
+#[cfg(feature = "nightly")]
 impl<T> std::ops::FromResidual<CommandResult<T>> for CommandResult<T>
 where
     T: Command + Serialize,
@@ -0,0 +1,250 @@
+use std::{
+    any::{Any, TypeId},
+    collections::HashMap,
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+};
+
+use axum::{
+    body::Body,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::{post, MethodRouter},
+};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    contracts::*,
+    input::CQRSInput,
+    problem::ProblemDetails,
+    registry::{ContractInfo, ContractKind},
+    validation::Validator,
+};
+
+/// A contract executed by a `Dispatcher` without a handler for it, sent as a 404 problem.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NotRegistered {
+    pub kind: ContractKind,
+    pub name: &'static str,
+}
+
+impl NotRegistered {
+    pub fn new(kind: ContractKind, name: &'static str) -> Self {
+        Self { kind, name }
+    }
+}
+
+impl fmt::Display for NotRegistered {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} `{}` is not registered",
+            self.kind.as_str(),
+            self.name
+        )
+    }
+}
+
+impl std::error::Error for NotRegistered {}
+
+impl IntoResponse for NotRegistered {
+    fn into_response(self) -> Response {
+        ProblemDetails::<()>::new(StatusCode::NOT_FOUND)
+            .with_detail(self.to_string())
+            .into_response()
+    }
+}
+
+type BoxFuture<T> = Pin<Box<dyn Future<Output = T> + Send>>;
+type CommandFn<C> = Arc<dyn Fn(C) -> BoxFuture<CommandResult<C>> + Send + Sync>;
+type QueryFn<Q, E> = Arc<dyn Fn(Q) -> BoxFuture<QueryResult<Q, E>> + Send + Sync>;
+
+/// Executes commands and queries in-process. Handlers take the state and the contract,
+/// e.g. `async fn create_site(state: AppState, command: CreateSite) -> CommandResult<CreateSite>`.
+///
+/// Mount it in a `CQRSRouter` to expose the same handlers over HTTP.
+pub struct Dispatcher<S = ()> {
+    state: S,
+    commands: HashMap<TypeId, Box<dyn Any + Send + Sync>>,
+    queries: HashMap<TypeId, Box<dyn Any + Send + Sync>>,
+    routes: Vec<DispatcherRoute>,
+}
+
+#[derive(Clone)]
+pub(crate) struct DispatcherRoute {
+    pub kind: ContractKind,
+    pub name: &'static str,
+    pub handler: &'static str,
+    pub info: Option<&'static ContractInfo>,
+    pub method_router: MethodRouter<(), Body>,
+}
+
+impl<S> Dispatcher<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    pub fn new(state: S) -> Self {
+        Self {
+            state,
+            commands: HashMap::new(),
+            queries: HashMap::new(),
+            routes: vec![],
+        }
+    }
+
+    pub fn state(&self) -> &S {
+        &self.state
+    }
+
+    pub fn command<H, Fut, C>(self, handler: H) -> Self
+    where
+        H: Fn(S, C) -> Fut + Send + Sync + 'static,
+        Fut: Future + Send + 'static,
+        Fut::Output: IntoCommandResult<C>,
+        C: Command + Serialize + DeserializeOwned + Send + 'static,
+        C::ErrorCodes: Send,
+    {
+        self.register_command(handler, None::<fn(&C) -> Vec<ValidationError<C>>>)
+    }
+
+    /// Registers a command whose input is checked by `validator` before `handler` is called.
+    pub fn validated_command<H, Fut, C, V>(self, handler: H, validator: V) -> Self
+    where
+        H: Fn(S, C) -> Fut + Send + Sync + 'static,
+        Fut: Future + Send + 'static,
+        Fut::Output: IntoCommandResult<C>,
+        C: Command + Serialize + DeserializeOwned + Send + 'static,
+        C::ErrorCodes: Send,
+        V: Validator<C> + 'static,
+    {
+        self.register_command(handler, Some(validator))
+    }
+
+    pub fn query<H, Fut, Q, E>(mut self, handler: H) -> Self
+    where
+        H: Fn(S, Q) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = QueryResult<Q, E>> + Send + 'static,
+        Q: Query + DeserializeOwned + Send + 'static,
+        Q::Result: Send,
+        E: Serialize + Send + 'static,
+    {
+        let state = self.state.clone();
+        let execute: QueryFn<Q, E> = Arc::new(move |query| Box::pin(handler(state.clone(), query)));
+
+        self.check_duplicate(ContractKind::Query, Q::name(), std::any::type_name::<H>());
+        self.queries
+            .insert(TypeId::of::<Q>(), Box::new(execute.clone()));
+        self.routes.push(DispatcherRoute {
+            kind: ContractKind::Query,
+            name: Q::name(),
+            handler: std::any::type_name::<H>(),
+            info: Q::info(),
+            method_router: post(move |CQRSInput(query): CQRSInput<Q>| execute(query)),
+        });
+        self
+    }
+
+    pub async fn execute_command<C>(&self, command: C) -> Result<CommandResult<C>, NotRegistered>
+    where
+        C: Command + Serialize + 'static,
+    {
+        let execute = self
+            .commands
+            .get(&TypeId::of::<C>())
+            .and_then(|h| h.downcast_ref::<CommandFn<C>>())
+            .ok_or_else(|| NotRegistered::new(ContractKind::Command, C::name()))?;
+        Ok(execute(command).await)
+    }
+
+    pub async fn execute_query<Q>(&self, query: Q) -> Result<QueryResult<Q>, NotRegistered>
+    where
+        Q: Query + 'static,
+    {
+        self.execute_fallible_query(query).await
+    }
+
+    /// Executes a query whose handler returns `QueryResult<Q, E>`. Panics if the handler of `Q` was
+    /// registered with a different error type.
+    pub async fn execute_fallible_query<Q, E>(
+        &self,
+        query: Q,
+    ) -> Result<QueryResult<Q, E>, NotRegistered>
+    where
+        Q: Query + 'static,
+        E: 'static,
+    {
+        let handler = self
+            .queries
+            .get(&TypeId::of::<Q>())
+            .ok_or_else(|| NotRegistered::new(ContractKind::Query, Q::name()))?;
+        let execute = handler.downcast_ref::<QueryFn<Q, E>>().unwrap_or_else(|| {
+            panic!(
+                "query `{}` is not registered with error type `{}`",
+                Q::name(),
+                std::any::type_name::<E>()
+            )
+        });
+        Ok(execute(query).await)
+    }
+
+    pub fn handles_command<C: Command + 'static>(&self) -> bool {
+        self.commands.contains_key(&TypeId::of::<C>())
+    }
+
+    pub fn handles_query<Q: Query + 'static>(&self) -> bool {
+        self.queries.contains_key(&TypeId::of::<Q>())
+    }
+
+    pub(crate) fn routes(&self) -> &[DispatcherRoute] {
+        &self.routes
+    }
+
+    fn register_command<H, Fut, C, V>(mut self, handler: H, validator: Option<V>) -> Self
+    where
+        H: Fn(S, C) -> Fut + Send + Sync + 'static,
+        Fut: Future + Send + 'static,
+        Fut::Output: IntoCommandResult<C>,
+        C: Command + Serialize + DeserializeOwned + Send + 'static,
+        C::ErrorCodes: Send,
+        V: Validator<C> + 'static,
+    {
+        let state = self.state.clone();
+        let execute: CommandFn<C> = Arc::new(move |command| {
+            if let Some(validator) = &validator {
+                let errors = validator.validate(&command);
+                if !errors.is_empty() {
+                    return Box::pin(std::future::ready(CommandResult::fail(errors)));
+                }
+            }
+            let result = handler(state.clone(), command);
+            Box::pin(async move { result.await.into_command_result() })
+        });
+
+        self.check_duplicate(ContractKind::Command, C::name(), std::any::type_name::<H>());
+        self.commands
+            .insert(TypeId::of::<C>(), Box::new(execute.clone()));
+        self.routes.push(DispatcherRoute {
+            kind: ContractKind::Command,
+            name: C::name(),
+            handler: std::any::type_name::<H>(),
+            info: C::info(),
+            method_router: post(move |CQRSInput(command): CQRSInput<C>| execute(command)),
+        });
+        self
+    }
+
+    fn check_duplicate(&self, kind: ContractKind, name: &str, handler: &str) {
+        if let Some(existing) = self
+            .routes
+            .iter()
+            .find(|r| r.kind == kind && r.name == name)
+        {
+            panic!(
+                "{:?} `{}` is registered twice: by `{}` and by `{}`",
+                kind, name, existing.handler, handler,
+            );
+        }
+    }
+}
@@ -0,0 +1,468 @@
+use std::{
+    collections::HashMap,
+    fmt, io,
+    sync::{Arc, Mutex},
+};
+
+use axum::async_trait;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    aggregate::Aggregate,
+    outbox::{OutboxMessage, OutboxStore, PendingMessage},
+};
+
+/// An event as recorded by an `EventStore`. `version` counts the events of its stream and
+/// `position` the events of the whole store, both from 1. `aggregate_type` is the `TYPE` of the
+/// aggregate that appended it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "PascalCase")]
+pub struct StoredEvent {
+    pub aggregate_type: String,
+    pub stream: String,
+    pub version: u64,
+    pub position: u64,
+    pub data: serde_json::Value,
+}
+
+impl StoredEvent {
+    /// Decodes the event if it was appended by `A`.
+    pub fn decode<A: Aggregate>(&self) -> Option<Result<A::Event, serde_json::Error>> {
+        (self.aggregate_type == A::TYPE).then(|| serde_json::from_value(self.data.clone()))
+    }
+}
+
+#[derive(Debug)]
+pub enum EventStoreError {
+    /// The stream was appended to since it was loaded.
+    Conflict {
+        stream: String,
+        expected_version: u64,
+        actual_version: u64,
+    },
+    Serialization(serde_json::Error),
+    Io(io::Error),
+}
+
+/// Streams of events with optimistic concurrency: events are only appended to a stream that is
+/// still at the version the caller has seen.
+#[async_trait]
+pub trait EventStore: Send + Sync {
+    /// The events of `stream` after `after_version`, in order.
+    async fn load(
+        &self,
+        stream: &str,
+        after_version: u64,
+    ) -> Result<Vec<StoredEvent>, EventStoreError>;
+
+    /// Appends `events` of an aggregate of `aggregate_type` if `stream` is at `expected_version`
+    /// (0 for a new stream) and returns its new version.
+    async fn append(
+        &self,
+        aggregate_type: &str,
+        stream: &str,
+        expected_version: u64,
+        events: Vec<serde_json::Value>,
+    ) -> Result<u64, EventStoreError> {
+        self.append_with_outbox(aggregate_type, stream, expected_version, events, vec![])
+            .await
+    }
+
+    /// Appends `events` like `append` and enqueues `messages` to the outbox of the store in the
+    /// same transaction, so that they are only published if the events were appended.
+    async fn append_with_outbox(
+        &self,
+        aggregate_type: &str,
+        stream: &str,
+        expected_version: u64,
+        events: Vec<serde_json::Value>,
+        messages: Vec<OutboxMessage>,
+    ) -> Result<u64, EventStoreError>;
+
+    /// At most `limit` events of all streams after `after_position`, in order.
+    async fn read_all(
+        &self,
+        after_position: u64,
+        limit: usize,
+    ) -> Result<Vec<StoredEvent>, EventStoreError>;
+}
+
+#[derive(Clone, Default)]
+pub struct InMemoryEventStore {
+    inner: Arc<Mutex<InMemoryEvents>>,
+}
+
+#[derive(Default)]
+struct InMemoryEvents {
+    events: Vec<StoredEvent>,
+    /// Indices of the events of each stream.
+    streams: HashMap<String, Vec<usize>>,
+    outbox: Vec<PendingMessage>,
+    last_message_id: u64,
+}
+
+impl InMemoryEventStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl EventStore for InMemoryEventStore {
+    async fn load(
+        &self,
+        stream: &str,
+        after_version: u64,
+    ) -> Result<Vec<StoredEvent>, EventStoreError> {
+        let inner = self.inner.lock().unwrap();
+        let indices = inner.streams.get(stream).map_or(&[][..], |i| &i[..]);
+        Ok(indices
+            .iter()
+            .skip(after_version as usize)
+            .map(|&i| inner.events[i].clone())
+            .collect())
+    }
+
+    async fn append_with_outbox(
+        &self,
+        aggregate_type: &str,
+        stream: &str,
+        expected_version: u64,
+        events: Vec<serde_json::Value>,
+        messages: Vec<OutboxMessage>,
+    ) -> Result<u64, EventStoreError> {
+        let mut inner = self.inner.lock().unwrap();
+        let InMemoryEvents {
+            events: all,
+            streams,
+            ..
+        } = &mut *inner;
+        let indices = streams.entry(stream.to_string()).or_default();
+
+        let actual_version = indices.len() as u64;
+        if actual_version != expected_version {
+            return Err(EventStoreError::Conflict {
+                stream: stream.to_string(),
+                expected_version,
+                actual_version,
+            });
+        }
+
+        for data in events {
+            indices.push(all.len());
+            all.push(StoredEvent {
+                aggregate_type: aggregate_type.to_string(),
+                stream: stream.to_string(),
+                version: indices.len() as u64,
+                position: all.len() as u64 + 1,
+                data,
+            });
+        }
+        let version = indices.len() as u64;
+        inner.push_messages(messages);
+        Ok(version)
+    }
+
+    async fn read_all(
+        &self,
+        after_position: u64,
+        limit: usize,
+    ) -> Result<Vec<StoredEvent>, EventStoreError> {
+        let inner = self.inner.lock().unwrap();
+        Ok(inner
+            .events
+            .iter()
+            .skip(after_position as usize)
+            .take(limit)
+            .cloned()
+            .collect())
+    }
+}
+
+impl InMemoryEvents {
+    fn push_messages(&mut self, messages: Vec<OutboxMessage>) {
+        for OutboxMessage { topic, payload } in messages {
+            self.last_message_id += 1;
+            self.outbox.push(PendingMessage {
+                id: self.last_message_id,
+                topic,
+                payload,
+            });
+        }
+    }
+}
+
+#[async_trait]
+impl OutboxStore for InMemoryEventStore {
+    async fn enqueue(&self, messages: Vec<OutboxMessage>) -> io::Result<()> {
+        self.inner.lock().unwrap().push_messages(messages);
+        Ok(())
+    }
+
+    async fn pending(&self, limit: usize) -> io::Result<Vec<PendingMessage>> {
+        let inner = self.inner.lock().unwrap();
+        Ok(inner.outbox.iter().take(limit).cloned().collect())
+    }
+
+    async fn delivered(&self, id: u64) -> io::Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.outbox.retain(|m| m.id != id);
+        Ok(())
+    }
+}
+
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteEventStore;
+
+#[cfg(feature = "sqlite")]
+mod sqlite {
+    use std::{io, path::Path};
+
+    use axum::async_trait;
+    use rusqlite::{params, Connection, Row, TransactionBehavior};
+
+    use super::{EventStore, EventStoreError, StoredEvent};
+    use crate::{
+        outbox::{OutboxMessage, OutboxStore, PendingMessage},
+        sqlite::SharedConnection,
+    };
+
+    /// Stores events in the `cqrs_events` table and outbox messages in the `cqrs_outbox` table,
+    /// which are created if they do not exist.
+    #[derive(Clone)]
+    pub struct SqliteEventStore {
+        connection: SharedConnection,
+    }
+
+    impl SqliteEventStore {
+        pub fn open(path: impl AsRef<Path>) -> rusqlite::Result<Self> {
+            Self::new(Connection::open(path)?)
+        }
+
+        pub fn new(connection: Connection) -> rusqlite::Result<Self> {
+            connection.execute_batch(
+                "CREATE TABLE IF NOT EXISTS cqrs_events (
+                    position INTEGER PRIMARY KEY AUTOINCREMENT,
+                    aggregate_type TEXT NOT NULL,
+                    stream TEXT NOT NULL,
+                    version INTEGER NOT NULL,
+                    data TEXT NOT NULL,
+                    UNIQUE (stream, version)
+                );
+                CREATE TABLE IF NOT EXISTS cqrs_outbox (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    topic TEXT NOT NULL,
+                    payload TEXT NOT NULL
+                )",
+            )?;
+            Ok(Self {
+                connection: SharedConnection::new(connection),
+            })
+        }
+    }
+
+    #[async_trait]
+    impl EventStore for SqliteEventStore {
+        async fn load(
+            &self,
+            stream: &str,
+            after_version: u64,
+        ) -> Result<Vec<StoredEvent>, EventStoreError> {
+            let stream = stream.to_string();
+            let rows = self
+                .connection
+                .run(move |connection| {
+                    let mut statement = connection.prepare(
+                        "SELECT aggregate_type, stream, version, position, data FROM cqrs_events
+                         WHERE stream = ?1 AND version > ?2 ORDER BY version",
+                    )?;
+                    let rows = statement.query_map(params![stream, after_version], read_row)?;
+                    rows.collect::<rusqlite::Result<Vec<_>>>()
+                })
+                .await
+                .map_err(EventStoreError::Io)?;
+            rows.into_iter().map(into_event).collect()
+        }
+
+        async fn append_with_outbox(
+            &self,
+            aggregate_type: &str,
+            stream: &str,
+            expected_version: u64,
+            events: Vec<serde_json::Value>,
+            messages: Vec<OutboxMessage>,
+        ) -> Result<u64, EventStoreError> {
+            let aggregate_type = aggregate_type.to_string();
+            let stream = stream.to_string();
+            let data = events
+                .iter()
+                .map(serde_json::to_string)
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(EventStoreError::Serialization)?;
+            let messages = serialize_messages(messages).map_err(EventStoreError::Serialization)?;
+
+            self.connection
+                .run(move |connection| {
+                    // take the write lock up front, so that concurrent writers see each other
+                    let tx =
+                        connection.transaction_with_behavior(TransactionBehavior::Immediate)?;
+                    let actual_version: u64 = tx.query_row(
+                        "SELECT COALESCE(MAX(version), 0) FROM cqrs_events WHERE stream = ?1",
+                        params![stream],
+                        |row| row.get(0),
+                    )?;
+                    if actual_version != expected_version {
+                        return Ok(Err(EventStoreError::Conflict {
+                            stream,
+                            expected_version,
+                            actual_version,
+                        }));
+                    }
+
+                    let mut version = actual_version;
+                    for data in data {
+                        version += 1;
+                        tx.execute(
+                            "INSERT INTO cqrs_events (aggregate_type, stream, version, data)
+                             VALUES (?1, ?2, ?3, ?4)",
+                            params![aggregate_type, stream, version, data],
+                        )?;
+                    }
+                    insert_messages(&tx, messages)?;
+                    tx.commit()?;
+                    Ok(Ok(version))
+                })
+                .await
+                .map_err(EventStoreError::Io)?
+        }
+
+        async fn read_all(
+            &self,
+            after_position: u64,
+            limit: usize,
+        ) -> Result<Vec<StoredEvent>, EventStoreError> {
+            let rows = self
+                .connection
+                .run(move |connection| {
+                    let mut statement = connection.prepare(
+                        "SELECT aggregate_type, stream, version, position, data FROM cqrs_events
+                         WHERE position > ?1 ORDER BY position LIMIT ?2",
+                    )?;
+                    let rows = statement.query_map(params![after_position, limit], read_row)?;
+                    rows.collect::<rusqlite::Result<Vec<_>>>()
+                })
+                .await
+                .map_err(EventStoreError::Io)?;
+            rows.into_iter().map(into_event).collect()
+        }
+    }
+
+    #[async_trait]
+    impl OutboxStore for SqliteEventStore {
+        async fn enqueue(&self, messages: Vec<OutboxMessage>) -> io::Result<()> {
+            let messages = serialize_messages(messages)?;
+            self.connection
+                .run(move |connection| insert_messages(connection, messages))
+                .await
+        }
+
+        async fn pending(&self, limit: usize) -> io::Result<Vec<PendingMessage>> {
+            let rows = self
+                .connection
+                .run(move |connection| {
+                    let mut statement = connection.prepare(
+                        "SELECT id, topic, payload FROM cqrs_outbox ORDER BY id LIMIT ?1",
+                    )?;
+                    let rows = statement.query_map(params![limit], |row| {
+                        Ok((row.get(0)?, row.get(1)?, row.get::<_, String>(2)?))
+                    })?;
+                    rows.collect::<rusqlite::Result<Vec<_>>>()
+                })
+                .await?;
+            rows.into_iter()
+                .map(|(id, topic, payload)| {
+                    Ok(PendingMessage {
+                        id,
+                        topic,
+                        payload: serde_json::from_str(&payload)?,
+                    })
+                })
+                .collect()
+        }
+
+        async fn delivered(&self, id: u64) -> io::Result<()> {
+            self.connection
+                .run(move |connection| {
+                    connection.execute("DELETE FROM cqrs_outbox WHERE id = ?1", params![id])?;
+                    Ok(())
+                })
+                .await
+        }
+    }
+
+    fn serialize_messages(
+        messages: Vec<OutboxMessage>,
+    ) -> Result<Vec<(String, String)>, serde_json::Error> {
+        messages
+            .into_iter()
+            .map(|m| Ok((m.topic, serde_json::to_string(&m.payload)?)))
+            .collect()
+    }
+
+    fn insert_messages(
+        connection: &Connection,
+        messages: Vec<(String, String)>,
+    ) -> rusqlite::Result<()> {
+        for (topic, payload) in messages {
+            connection.execute(
+                "INSERT INTO cqrs_outbox (topic, payload) VALUES (?1, ?2)",
+                params![topic, payload],
+            )?;
+        }
+        Ok(())
+    }
+
+    type EventRow = (String, String, u64, u64, String);
+
+    fn read_row(row: &Row<'_>) -> rusqlite::Result<EventRow> {
+        Ok((
+            row.get(0)?,
+            row.get(1)?,
+            row.get(2)?,
+            row.get(3)?,
+            row.get(4)?,
+        ))
+    }
+
+    fn into_event(
+        (aggregate_type, stream, version, position, data): EventRow,
+    ) -> Result<StoredEvent, EventStoreError> {
+        Ok(StoredEvent {
+            aggregate_type,
+            stream,
+            version,
+            position,
+            data: serde_json::from_str(&data).map_err(EventStoreError::Serialization)?,
+        })
+    }
+}
+
+impl fmt::Display for EventStoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EventStoreError::Conflict {
+                stream,
+                expected_version,
+                actual_version,
+            } => write!(
+                f,
+                "stream `{}` is at version {}, expected {}",
+                stream, actual_version, expected_version
+            ),
+            EventStoreError::Serialization(e) => write!(f, "cannot (de)serialize an event: {}", e),
+            EventStoreError::Io(e) => write!(f, "cannot access the event store: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for EventStoreError {}
@@ -0,0 +1,193 @@
+use axum::{
+    body::HttpBody,
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    routing::MethodRouter,
+};
+use serde::{de::DeserializeOwned, Serialize};
+
+/// A wire format of contracts. The request format is chosen from `Content-Type`, the response
+/// format from `Accept`; JSON is the default for both.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    #[cfg(feature = "msgpack")]
+    MessagePack,
+    #[cfg(feature = "cbor")]
+    Cbor,
+}
+
+impl Format {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Format::Json => "application/json",
+            #[cfg(feature = "msgpack")]
+            Format::MessagePack => "application/msgpack",
+            #[cfg(feature = "cbor")]
+            Format::Cbor => "application/cbor",
+        }
+    }
+
+    /// Parameters such as `charset` are ignored. Returns `None` for unsupported media types.
+    pub fn from_media_type(media_type: &str) -> Option<Self> {
+        let media_type = media_type.split(';').next()?.trim().to_ascii_lowercase();
+        let (kind, subtype) = media_type.split_once('/')?;
+        if kind != "application" {
+            return None;
+        }
+
+        match subtype {
+            "json" => Some(Format::Json),
+            #[cfg(feature = "msgpack")]
+            "msgpack" | "x-msgpack" | "vnd.msgpack" => Some(Format::MessagePack),
+            #[cfg(feature = "cbor")]
+            "cbor" => Some(Format::Cbor),
+            _ if subtype.ends_with("+json") => Some(Format::Json),
+            _ => None,
+        }
+    }
+
+    pub fn from_content_type(headers: &HeaderMap) -> Option<Self> {
+        let content_type = headers.get(header::CONTENT_TYPE)?.to_str().ok()?;
+        Self::from_media_type(content_type)
+    }
+
+    /// The supported format the client prefers, by quality. Falls back to JSON, also when the
+    /// client accepts none of the supported formats.
+    pub fn from_accept(headers: &HeaderMap) -> Self {
+        let mut accepted: Vec<(f32, Format)> = headers
+            .get_all(header::ACCEPT)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .filter_map(|range| {
+                let quality = range
+                    .split(';')
+                    .skip(1)
+                    .find_map(|p| p.trim().strip_prefix("q="))
+                    .and_then(|q| q.parse().ok())
+                    .unwrap_or(1.0);
+                Some((quality, Self::from_media_type(range)?))
+            })
+            .filter(|(quality, _)| *quality > 0.0)
+            .collect();
+
+        accepted.sort_by(|a, b| b.0.total_cmp(&a.0));
+        accepted.first().map_or(Format::Json, |(_, format)| *format)
+    }
+
+    /// Deserializes `T` straight from the format, so that e.g. MessagePack `bin` values and CBOR
+    /// tags reach it as they were sent.
+    pub fn decode<T: DeserializeOwned>(self, bytes: &[u8]) -> Result<T, String> {
+        match self {
+            Format::Json => serde_json::from_slice(bytes).map_err(|e| e.to_string()),
+            #[cfg(feature = "msgpack")]
+            Format::MessagePack => rmp_serde::from_slice(bytes).map_err(|e| e.to_string()),
+            #[cfg(feature = "cbor")]
+            Format::Cbor => ciborium::de::from_reader(bytes).map_err(|e| e.to_string()),
+        }
+    }
+
+    pub fn encode<T: Serialize + ?Sized>(self, value: &T) -> Result<Vec<u8>, String> {
+        match self {
+            Format::Json => serde_json::to_vec(value).map_err(|e| e.to_string()),
+            #[cfg(feature = "msgpack")]
+            Format::MessagePack => rmp_serde::to_vec_named(value).map_err(|e| e.to_string()),
+            #[cfg(feature = "cbor")]
+            Format::Cbor => {
+                let mut bytes = vec![];
+                ciborium::ser::into_writer(value, &mut bytes).map_err(|e| e.to_string())?;
+                Ok(bytes)
+            }
+        }
+    }
+}
+
+tokio::task_local! {
+    static RESPONSE_FORMAT: Format;
+}
+
+/// Serializes a response body straight into the format negotiated for the request, or JSON outside
+/// of a contract route. `json_content_type` is sent when the format is JSON, e.g. for problems.
+pub(crate) fn respond<T>(status: StatusCode, value: &T, json_content_type: &'static str) -> Response
+where
+    T: Serialize + ?Sized,
+{
+    let format = RESPONSE_FORMAT.try_with(|f| *f).unwrap_or(Format::Json);
+    let content_type = if format == Format::Json {
+        json_content_type
+    } else {
+        format.content_type()
+    };
+
+    match format.encode(value) {
+        Ok(bytes) => (
+            status,
+            [(header::CONTENT_TYPE, HeaderValue::from_static(content_type))],
+            bytes,
+        )
+            .into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e).into_response(),
+    }
+}
+
+/// Encodes the responses of a contract route, problems included, in the format the client accepts.
+/// Clients that accept none of the supported formats get JSON.
+pub(crate) fn negotiated<S, B>(method_router: MethodRouter<S, B>) -> MethodRouter<S, B>
+where
+    S: Clone + Send + Sync + 'static,
+    B: HttpBody + Send + 'static,
+{
+    #[cfg(any(feature = "msgpack", feature = "cbor"))]
+    let method_router = method_router.layer(axum::middleware::from_fn(negotiate));
+
+    method_router
+}
+
+#[cfg(any(feature = "msgpack", feature = "cbor"))]
+async fn negotiate<B>(
+    request: axum::http::Request<B>,
+    next: axum::middleware::Next<B>,
+) -> Response {
+    let format = Format::from_accept(request.headers());
+    let mut response = RESPONSE_FORMAT.scope(format, next.run(request)).await;
+    response
+        .headers_mut()
+        .append(header::VARY, HeaderValue::from_static("accept"));
+
+    let encoded_in = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(Format::from_media_type);
+    match encoded_in {
+        Some(encoded_in) if encoded_in != format => transcode(response, encoded_in, format).await,
+        _ => response,
+    }
+}
+
+/// Re-encodes a response stored in another format, e.g. one replayed for an idempotency key. This
+/// goes through `serde_json::Value`, so e.g. binary values arrive as arrays of numbers.
+#[cfg(any(feature = "msgpack", feature = "cbor"))]
+async fn transcode(response: Response, from: Format, to: Format) -> Response {
+    use axum::body::Full;
+
+    let (mut parts, body) = response.into_parts();
+    let bytes = match hyper::body::to_bytes(body).await {
+        Ok(bytes) => bytes,
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    };
+
+    let value = from.decode::<serde_json::Value>(&bytes);
+    match value.and_then(|v| to.encode(&v)) {
+        Ok(encoded) => {
+            parts.headers.remove(header::CONTENT_LENGTH);
+            parts.headers.insert(
+                header::CONTENT_TYPE,
+                HeaderValue::from_static(to.content_type()),
+            );
+            (parts, Full::from(encoded)).into_response()
+        }
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e).into_response(),
+    }
+}
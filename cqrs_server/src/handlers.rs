@@ -1,66 +1,690 @@
-use crate::{contracts::*, input::CQRSInput};
-use axum::{body::HttpBody, handler::Handler, routing::post, Router};
-use serde::Serialize;
-use std::future::Future;
+use crate::{
+    batch::BatchQueries,
+    cache::{cached, invalidating},
+    contracts::*,
+    dispatcher::Dispatcher,
+    format::negotiated,
+    idempotency::idempotent,
+    input::CQRSInput,
+    introspection::introspection_route,
+    metrics::{instrumented, metrics_route},
+    outbox::outboxed,
+    problem::{correlated, InternalError},
+    registry::{ContractInfo, ContractKind},
+    routing::RouteNaming,
+    trace::traced,
+    validation::{InputValidator, Validator},
+};
+use axum::{
+    body::{Body, Bytes, HttpBody},
+    extract::FromRequestParts,
+    response::{IntoResponse, Response},
+    routing::{post, MethodRouter},
+    BoxError, Extension, Router,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{fmt, future::Future, time::Duration};
 
-pub trait CommandHandler<M, T> {}
-pub trait QueryHandler<M, T> {}
+/// An async function that handles the command `C`: it takes extractors followed by a
+/// `CQRSInput<C>` and returns a `CommandResponse<C, _>`.
+pub trait CommandHandler<M, C, S, B>: Clone + Send + Sized + 'static {
+    #[doc(hidden)]
+    fn into_method_router(self) -> MethodRouter<S, B>;
+}
+
+/// An async function that handles the query `Q`: it takes extractors followed by a
+/// `CQRSInput<Q>` and returns a `QueryResponse<Q>`.
+pub trait QueryHandler<M, Q, S, B>: Clone + Send + Sized + 'static {
+    #[doc(hidden)]
+    fn into_method_router(self) -> MethodRouter<S, B>;
+}
+
+/// What a command handler returns: anything `IntoCommandResult<C>`, or a `Result` of it whose
+/// error converts into an `InternalError`, e.g. an `error_stack::Report`.
+pub trait CommandResponse<C, M> {
+    fn into_command_response(self) -> Response;
+}
+
+/// What a query handler returns: a `QueryResult<Q, E>`, or a `Result` of it whose error converts
+/// into an `InternalError`, e.g. an `error_stack::Report`.
+pub trait QueryResponse<Q> {
+    fn into_query_response(self) -> Response;
+}
+
+impl<C, R> CommandResponse<C, ()> for R
+where
+    C: Command + Serialize,
+    R: IntoCommandResult<C>,
+{
+    fn into_command_response(self) -> Response {
+        self.into_command_result().into_response()
+    }
+}
+
+impl<C, R, Err> CommandResponse<C, Result<(), Err>> for Result<R, Err>
+where
+    C: Command + Serialize,
+    R: IntoCommandResult<C>,
+    Err: Into<InternalError>,
+{
+    fn into_command_response(self) -> Response {
+        match self {
+            Ok(result) => result.into_command_result().into_response(),
+            Err(error) => error.into().into_response(),
+        }
+    }
+}
+
+impl<Q, E> QueryResponse<Q> for QueryResult<Q, E>
+where
+    Q: Query,
+    E: Serialize,
+{
+    fn into_query_response(self) -> Response {
+        self.into_response()
+    }
+}
+
+impl<Q, E, Err> QueryResponse<Q> for Result<QueryResult<Q, E>, Err>
+where
+    Q: Query,
+    E: Serialize,
+    Err: Into<InternalError>,
+{
+    fn into_query_response(self) -> Response {
+        match self {
+            Ok(result) => result.into_response(),
+            Err(error) => error.into().into_response(),
+        }
+    }
+}
 
+/// Registers contracts on a `CQRSRouter`, which reports a contract registered twice with both of
+/// its handlers.
 pub trait CQRSBuilder<S, B> {
-    fn command<H, T, TC, R>(self, handler: H) -> Self
+    fn command<H, C, M>(self, handler: H) -> Self
+    where
+        C: Command,
+        H: CommandHandler<M, C, S, B>;
+
+    /// Registers a command whose input is checked by `validator` before `handler` is called.
+    fn validated_command<H, C, M, V>(self, handler: H, validator: V) -> Self
+    where
+        C: Command + Serialize + 'static,
+        H: CommandHandler<M, C, S, B>,
+        V: Validator<C> + 'static;
+
+    fn query<H, Q, M>(self, handler: H) -> Self
     where
-        TC: Command,
-        H: Handler<T, S, B> + CommandHandler<R, TC>,
-        T: 'static;
+        Q: Query,
+        H: QueryHandler<M, Q, S, B>;
 
-    fn query<H, T, Q, R>(self, handler: H) -> Self
+    /// Registers a query whose successful results are served from the `QueryCache` for `ttl`.
+    fn cached_query<H, Q, M>(self, handler: H, ttl: Duration) -> Self
     where
         Q: Query,
-        H: Handler<T, S, B> + QueryHandler<R, Q>,
-        T: 'static;
+        H: QueryHandler<M, Q, S, B>,
+        B: HttpBody + From<Bytes> + Send + 'static,
+        B::Data: Send,
+        B::Error: Into<BoxError>;
+
+    /// Serves the `Metrics` of the router on `route`, e.g. `/metrics`, in the Prometheus text
+    /// format. A contract on the same route is a `DuplicateContract` when the router is built.
+    fn metrics(self, route: &str) -> Self;
 }
 
-impl<S, B> CQRSBuilder<S, B> for Router<S, B>
+/// Registers contracts on a plain `Router`, which does not track what it registers: a contract
+/// registered twice panics with axum's overlapping route error instead of a `DuplicateContract`.
+#[deprecated(note = "register contracts on a `CQRSRouter`, which detects duplicate contracts")]
+pub trait RouterContracts<S, B> {
+    fn command<H, C, M>(self, handler: H) -> Self
+    where
+        C: Command,
+        H: CommandHandler<M, C, S, B>;
+
+    /// Registers a command whose input is checked by `validator` before `handler` is called.
+    fn validated_command<H, C, M, V>(self, handler: H, validator: V) -> Self
+    where
+        C: Command + Serialize + 'static,
+        H: CommandHandler<M, C, S, B>,
+        V: Validator<C> + 'static;
+
+    fn query<H, Q, M>(self, handler: H) -> Self
+    where
+        Q: Query,
+        H: QueryHandler<M, Q, S, B>;
+
+    /// Registers a query whose successful results are served from the `QueryCache` for `ttl`.
+    fn cached_query<H, Q, M>(self, handler: H, ttl: Duration) -> Self
+    where
+        Q: Query,
+        H: QueryHandler<M, Q, S, B>,
+        B: HttpBody + From<Bytes> + Send + 'static,
+        B::Data: Send,
+        B::Error: Into<BoxError>;
+
+    /// Serves the `Metrics` of the router on `route`, e.g. `/metrics`, in the Prometheus text
+    /// format.
+    fn metrics(self, route: &str) -> Self;
+}
+
+pub struct CQRSRouter<S = (), B = Body> {
+    router: Router<S, B>,
+    naming: RouteNaming,
+    registered: Vec<RegisteredContract>,
+    metrics: Option<String>,
+    introspection: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+pub struct RegisteredContract {
+    pub kind: ContractKind,
+    pub name: &'static str,
+    pub route: String,
+    pub handler: &'static str,
+    pub info: Option<&'static ContractInfo>,
+}
+
+#[derive(Debug)]
+pub struct DuplicateContract {
+    pub existing: Box<RegisteredContract>,
+    pub duplicate: Box<RegisteredContract>,
+}
+
+impl<S, B> CQRSRouter<S, B>
 where
     S: Clone + Send + Sync + 'static,
-    B: HttpBody + Send + 'static,
+    B: HttpBody + From<Bytes> + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
 {
-    fn command<H, T, C, R>(self, handler: H) -> Self
+    pub fn new() -> Self {
+        Self::with_naming(RouteNaming::default())
+    }
+
+    pub fn with_naming(naming: RouteNaming) -> Self {
+        Self {
+            router: Router::new(),
+            naming,
+            registered: vec![],
+            metrics: None,
+            introspection: None,
+        }
+    }
+
+    pub fn naming(&self) -> &RouteNaming {
+        &self.naming
+    }
+
+    pub fn registered(&self) -> &[RegisteredContract] {
+        &self.registered
+    }
+
+    /// Serves the kind, name, route and metadata of every registered contract as JSON on `route`,
+    /// e.g. `/contracts`, including the ones registered after this call. A contract on the same
+    /// route is a `DuplicateContract` when the router is built.
+    pub fn introspection(mut self, route: impl Into<String>) -> Self {
+        self.introspection = Some(route.into());
+        self
+    }
+
+    /// Serves the queries registered so far in batches on `route`, with the naming of this router.
+    /// See `BatchQueries::wrap`.
+    pub fn batch_queries(&self, route: impl Into<String>) -> BatchQueries {
+        self.registered
+            .iter()
+            .filter(|r| r.kind == ContractKind::Query)
+            .fold(
+                BatchQueries::new(route).naming(self.naming.clone()),
+                |batch, r| batch.named(r.name),
+            )
+    }
+
+    pub fn try_into_router(self) -> Result<Router<S, B>, DuplicateContract> {
+        let endpoints = [
+            self.metrics
+                .as_ref()
+                .map(|route| endpoint("metrics", route, "CQRSRouter::metrics")),
+            self.introspection
+                .as_ref()
+                .map(|route| endpoint("introspection", route, "CQRSRouter::introspection")),
+        ];
+
+        let mut taken = self.registered.clone();
+        for endpoint in endpoints.into_iter().flatten() {
+            if let Some(duplicate) = duplicate(&taken, &endpoint) {
+                return Err(duplicate);
+            }
+            taken.push(endpoint);
+        }
+
+        let mut router = self.router;
+        if let Some(route) = &self.metrics {
+            router = router.route(route, metrics_route());
+        }
+        if let Some(route) = &self.introspection {
+            router = router.route(route, introspection_route(&self.registered));
+        }
+        Ok(router)
+    }
+
+    pub fn into_router(self) -> Router<S, B> {
+        self.try_into_router().unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn try_command<H, C, M>(self, handler: H) -> Result<Self, DuplicateContract>
     where
         C: Command,
-        H: Handler<T, S, B> + CommandHandler<R, C>,
-        T: 'static,
+        H: CommandHandler<M, C, S, B>,
+    {
+        let contract = self.command_contract::<H, C>();
+        self.register(contract, handler.into_method_router())
+    }
+
+    pub fn try_validated_command<H, C, M, V>(
+        self,
+        handler: H,
+        validator: V,
+    ) -> Result<Self, DuplicateContract>
+    where
+        C: Command + Serialize + 'static,
+        H: CommandHandler<M, C, S, B>,
+        V: Validator<C> + 'static,
+    {
+        let contract = self.command_contract::<H, C>();
+        self.register(
+            contract,
+            handler
+                .into_method_router()
+                .layer(validator_layer(validator)),
+        )
+    }
+
+    pub fn try_query<H, Q, M>(self, handler: H) -> Result<Self, DuplicateContract>
+    where
+        Q: Query,
+        H: QueryHandler<M, Q, S, B>,
     {
-        self.route(&format!("/command/{}", C::name()), post(handler))
+        let contract = self.query_contract::<H, Q>();
+        self.register(contract, handler.into_method_router())
     }
 
-    fn query<H, T, Q, R>(self, handler: H) -> Self
+    pub fn try_cached_query<H, Q, M>(
+        self,
+        handler: H,
+        ttl: Duration,
+    ) -> Result<Self, DuplicateContract>
     where
         Q: Query,
-        H: Handler<T, S, B> + QueryHandler<R, Q>,
-        T: 'static,
+        H: QueryHandler<M, Q, S, B>,
+        B: HttpBody + From<Bytes> + Send + 'static,
+        B::Data: Send,
+        B::Error: Into<BoxError>,
     {
-        self.route(&format!("/query/{}", Q::name()), post(handler))
+        let contract = self.query_contract::<H, Q>();
+        self.register(
+            contract,
+            cached(Q::name(), ttl, handler.into_method_router()),
+        )
+    }
+
+    fn query_contract<H, Q: Query>(&self) -> RegisteredContract {
+        RegisteredContract {
+            kind: ContractKind::Query,
+            name: Q::name(),
+            route: self.naming.query::<Q>(),
+            handler: std::any::type_name::<H>(),
+            info: Q::info(),
+        }
+    }
+
+    fn command_contract<H, C: Command>(&self) -> RegisteredContract {
+        RegisteredContract {
+            kind: ContractKind::Command,
+            name: C::name(),
+            route: self.naming.command::<C>(),
+            handler: std::any::type_name::<H>(),
+            info: C::info(),
+        }
+    }
+
+    fn register(
+        mut self,
+        contract: RegisteredContract,
+        method_router: MethodRouter<S, B>,
+    ) -> Result<Self, DuplicateContract> {
+        if let Some(duplicate) = duplicate(&self.registered, &contract) {
+            return Err(duplicate);
+        }
+
+        let method_router = match contract.kind {
+            ContractKind::Command => {
+                idempotent(outboxed(invalidating(contract.name, method_router)))
+            }
+            _ => method_router,
+        };
+        self.router = self.router.route(
+            &contract.route,
+            contract_route(contract.kind, contract.name, method_router),
+        );
+        self.registered.push(contract);
+        Ok(self)
     }
 }
 
+impl<S> CQRSRouter<S, Body>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    /// Exposes every contract registered in `dispatcher` over HTTP.
+    pub fn try_mount<DS>(mut self, dispatcher: &Dispatcher<DS>) -> Result<Self, DuplicateContract>
+    where
+        DS: Clone + Send + Sync + 'static,
+    {
+        for route in dispatcher.routes() {
+            let contract = RegisteredContract {
+                kind: route.kind,
+                name: route.name,
+                route: self
+                    .naming
+                    .route(route.kind, route.name)
+                    .expect("only commands and queries are dispatched"),
+                handler: route.handler,
+                info: route.info,
+            };
+            self = self.register(contract, route.method_router.clone().with_state(()))?;
+        }
+        Ok(self)
+    }
+
+    pub fn mount<DS>(self, dispatcher: &Dispatcher<DS>) -> Self
+    where
+        DS: Clone + Send + Sync + 'static,
+    {
+        self.try_mount(dispatcher)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+}
+
+impl<S, B> Default for CQRSRouter<S, B>
+where
+    S: Clone + Send + Sync + 'static,
+    B: HttpBody + From<Bytes> + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S, B> From<CQRSRouter<S, B>> for Router<S, B>
+where
+    S: Clone + Send + Sync + 'static,
+    B: HttpBody + From<Bytes> + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+{
+    fn from(value: CQRSRouter<S, B>) -> Self {
+        value.into_router()
+    }
+}
+
+impl<S, B> CQRSBuilder<S, B> for CQRSRouter<S, B>
+where
+    S: Clone + Send + Sync + 'static,
+    B: HttpBody + From<Bytes> + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+{
+    fn command<H, C, M>(self, handler: H) -> Self
+    where
+        C: Command,
+        H: CommandHandler<M, C, S, B>,
+    {
+        self.try_command(handler)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    fn validated_command<H, C, M, V>(self, handler: H, validator: V) -> Self
+    where
+        C: Command + Serialize + 'static,
+        H: CommandHandler<M, C, S, B>,
+        V: Validator<C> + 'static,
+    {
+        self.try_validated_command(handler, validator)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    fn query<H, Q, M>(self, handler: H) -> Self
+    where
+        Q: Query,
+        H: QueryHandler<M, Q, S, B>,
+    {
+        self.try_query(handler).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    fn cached_query<H, Q, M>(self, handler: H, ttl: Duration) -> Self
+    where
+        Q: Query,
+        H: QueryHandler<M, Q, S, B>,
+        B: HttpBody + From<Bytes> + Send + 'static,
+        B::Data: Send,
+        B::Error: Into<BoxError>,
+    {
+        self.try_cached_query(handler, ttl)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    fn metrics(mut self, route: &str) -> Self {
+        self.metrics = Some(route.to_string());
+        self
+    }
+}
+
+#[allow(deprecated)]
+impl<S, B> RouterContracts<S, B> for Router<S, B>
+where
+    S: Clone + Send + Sync + 'static,
+    B: HttpBody + From<Bytes> + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+{
+    fn command<H, C, M>(self, handler: H) -> Self
+    where
+        C: Command,
+        H: CommandHandler<M, C, S, B>,
+    {
+        self.route(
+            &RouteNaming::default().command::<C>(),
+            contract_route(
+                ContractKind::Command,
+                C::name(),
+                idempotent(outboxed(invalidating(
+                    C::name(),
+                    handler.into_method_router(),
+                ))),
+            ),
+        )
+    }
+
+    fn validated_command<H, C, M, V>(self, handler: H, validator: V) -> Self
+    where
+        C: Command + Serialize + 'static,
+        H: CommandHandler<M, C, S, B>,
+        V: Validator<C> + 'static,
+    {
+        self.route(
+            &RouteNaming::default().command::<C>(),
+            contract_route(
+                ContractKind::Command,
+                C::name(),
+                idempotent(outboxed(invalidating(
+                    C::name(),
+                    handler
+                        .into_method_router()
+                        .layer(validator_layer(validator)),
+                ))),
+            ),
+        )
+    }
+
+    fn query<H, Q, M>(self, handler: H) -> Self
+    where
+        Q: Query,
+        H: QueryHandler<M, Q, S, B>,
+    {
+        self.route(
+            &RouteNaming::default().query::<Q>(),
+            contract_route(ContractKind::Query, Q::name(), handler.into_method_router()),
+        )
+    }
+
+    fn cached_query<H, Q, M>(self, handler: H, ttl: Duration) -> Self
+    where
+        Q: Query,
+        H: QueryHandler<M, Q, S, B>,
+        B: HttpBody + From<Bytes> + Send + 'static,
+        B::Data: Send,
+        B::Error: Into<BoxError>,
+    {
+        self.route(
+            &RouteNaming::default().query::<Q>(),
+            contract_route(
+                ContractKind::Query,
+                Q::name(),
+                cached(Q::name(), ttl, handler.into_method_router()),
+            ),
+        )
+    }
+
+    fn metrics(self, route: &str) -> Self {
+        self.route(route, metrics_route())
+    }
+}
+
+/// Wraps the route of a contract in the layers every contract shares.
+fn contract_route<S, B>(
+    kind: ContractKind,
+    name: &'static str,
+    method_router: MethodRouter<S, B>,
+) -> MethodRouter<S, B>
+where
+    S: Clone + Send + Sync + 'static,
+    B: HttpBody + Send + 'static,
+{
+    traced(
+        kind,
+        name,
+        instrumented(kind, name, negotiated(correlated(method_router))),
+    )
+}
+
+fn duplicate(
+    registered: &[RegisteredContract],
+    contract: &RegisteredContract,
+) -> Option<DuplicateContract> {
+    let existing = registered.iter().find(|r| {
+        (r.kind == contract.kind && r.name == contract.name) || r.route == contract.route
+    })?;
+    Some(DuplicateContract {
+        existing: Box::new(existing.clone()),
+        duplicate: Box::new(contract.clone()),
+    })
+}
+
+/// A route of `CQRSRouter` that is not a contract, checked for clashes like one.
+fn endpoint(name: &'static str, route: &str, handler: &'static str) -> RegisteredContract {
+    RegisteredContract {
+        kind: ContractKind::Query,
+        name,
+        route: route.to_string(),
+        handler,
+        info: None,
+    }
+}
+
+fn validator_layer<C, V>(validator: V) -> Extension<InputValidator>
+where
+    C: Command + Serialize + 'static,
+    V: Validator<C> + 'static,
+{
+    Extension(InputValidator::new(validator))
+}
+
+impl fmt::Display for DuplicateContract {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.existing.kind == self.duplicate.kind && self.existing.name == self.duplicate.name {
+            write!(
+                f,
+                "{:?} `{}` is registered twice: by `{}` and by `{}`",
+                self.duplicate.kind,
+                self.duplicate.name,
+                self.existing.handler,
+                self.duplicate.handler,
+            )
+        } else {
+            write!(
+                f,
+                "route `{}` of {:?} `{}` (handler `{}`) clashes with {:?} `{}` (handler `{}`)",
+                self.duplicate.route,
+                self.duplicate.kind,
+                self.duplicate.name,
+                self.duplicate.handler,
+                self.existing.kind,
+                self.existing.name,
+                self.existing.handler,
+            )
+        }
+    }
+}
+
+impl std::error::Error for DuplicateContract {}
+
 macro_rules! impl_handlers {
     (
         [$($ty:ident),*], $last:ident
     ) => {
-        impl<F, Fut, $($ty,)* $last> CommandHandler<($($ty,)* $last,), $last> for F
+        impl<F, Fut, M, S, B, $($ty,)* $last> CommandHandler<(M, $($ty,)* $last,), $last, S, B>
+            for F
         where
-            F: FnOnce($($ty,)* CQRSInput<$last>,) -> Fut,
-            Fut: Future<Output = CommandResult<$last>> ,
-            $last: Command + Serialize,
-        {}
+            F: FnOnce($($ty,)* CQRSInput<$last>,) -> Fut + Clone + Send + 'static,
+            Fut: Future + Send,
+            Fut::Output: CommandResponse<$last, M>,
+            S: Clone + Send + Sync + 'static,
+            B: HttpBody + Send + 'static,
+            B::Data: Send,
+            B::Error: Into<BoxError>,
+            $($ty: FromRequestParts<S> + Send + 'static,)*
+            $last: Command + Serialize + DeserializeOwned + Send + 'static,
+        {
+            #[allow(non_snake_case)]
+            fn into_method_router(self) -> MethodRouter<S, B> {
+                post(move |$($ty: $ty,)* input: CQRSInput<$last>| async move {
+                    self($($ty,)* input).await.into_command_response()
+                })
+            }
+        }
 
-        impl<F, Fut, $($ty,)* $last> QueryHandler<($($ty,)* $last,), $last> for F
+        impl<F, Fut, S, B, $($ty,)* $last> QueryHandler<($($ty,)* $last,), $last, S, B> for F
         where
-            F: FnOnce($($ty,)* CQRSInput<$last>,) -> Fut,
-            Fut: Future<Output = QueryResult<$last>> ,
-            $last: Query + Serialize,
-        {}
+            F: FnOnce($($ty,)* CQRSInput<$last>,) -> Fut + Clone + Send + 'static,
+            Fut: Future + Send,
+            Fut::Output: QueryResponse<$last>,
+            S: Clone + Send + Sync + 'static,
+            B: HttpBody + Send + 'static,
+            B::Data: Send,
+            B::Error: Into<BoxError>,
+            $($ty: FromRequestParts<S> + Send + 'static,)*
+            $last: Query + DeserializeOwned + Send + 'static,
+        {
+            #[allow(non_snake_case)]
+            fn into_method_router(self) -> MethodRouter<S, B> {
+                post(move |$($ty: $ty,)* input: CQRSInput<$last>| async move {
+                    self($($ty,)* input).await.into_query_response()
+                })
+            }
+        }
     };
 }
 
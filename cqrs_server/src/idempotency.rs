@@ -0,0 +1,557 @@
+use std::{
+    collections::HashMap,
+    io,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    async_trait,
+    body::{Bytes, HttpBody},
+    http::{header, HeaderValue, Request, StatusCode},
+    middleware::{from_fn, Next},
+    response::{IntoResponse, Response},
+    routing::MethodRouter,
+    BoxError,
+};
+use error_stack::Report;
+use http_body::{LengthLimitError, Limited};
+
+use crate::{
+    format::Format,
+    problem::{InternalError, ProblemDetails},
+};
+
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+pub const IDEMPOTENT_REPLAYED_HEADER: &str = "idempotent-replayed";
+
+/// Bodies larger than this are not buffered, the default body limit of axum.
+pub const MAX_BUFFERED_BODY: usize = 2 * 1024 * 1024;
+
+/// A response, stored under its idempotency key or in a query cache.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StoredResponse {
+    pub status: u16,
+    pub content_type: Option<String>,
+    pub body: Vec<u8>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Reservation {
+    /// The key is now reserved for the caller, which must `complete` or `release` it.
+    Acquired,
+    InProgress,
+    Completed(StoredResponse),
+    /// The key was used for a request with another fingerprint.
+    Mismatched,
+}
+
+/// Stores the responses of commands by idempotency key, with the fingerprint of the request that
+/// used the key. Expired keys behave as if they were never used.
+#[async_trait]
+pub trait IdempotencyStore: Send + Sync {
+    /// Atomically reserves `key` for `lock_ttl`, unless it is already reserved or completed.
+    /// Returns `Mismatched` if the key is in use with another `fingerprint`.
+    async fn reserve(
+        &self,
+        key: &str,
+        fingerprint: &str,
+        lock_ttl: Duration,
+    ) -> io::Result<Reservation>;
+
+    async fn complete(
+        &self,
+        key: &str,
+        fingerprint: &str,
+        response: StoredResponse,
+        ttl: Duration,
+    ) -> io::Result<()>;
+
+    /// Drops the reservation of `key`, so that the command can be retried.
+    async fn release(&self, key: &str) -> io::Result<()>;
+}
+
+/// Makes commands honour the `Idempotency-Key` header: the first response to a key is stored and
+/// replayed for repeated requests, without calling the handler again. Only successful responses
+/// are stored, failed ones (e.g. malformed input or internal errors) can be retried.
+///
+/// Keys are scoped by route. Reusing a key for another input is rejected with 422. Inputs and
+/// responses are buffered up to `MAX_BUFFERED_BODY`: a larger input is rejected with 413, a larger
+/// response is sent without being stored. Enable it for the command routes of `CQRSBuilder` with
+/// `router.layer(Extension(Idempotency::new(store)))`.
+#[derive(Clone)]
+pub struct Idempotency {
+    store: Arc<dyn IdempotencyStore>,
+    ttl: Duration,
+    lock_ttl: Duration,
+}
+
+impl Idempotency {
+    pub fn new(store: impl IdempotencyStore + 'static) -> Self {
+        Self {
+            store: Arc::new(store),
+            ttl: Duration::from_secs(24 * 60 * 60),
+            lock_ttl: Duration::from_secs(60),
+        }
+    }
+
+    /// How long a response is replayed. Defaults to 24 hours.
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// How long a key is reserved while its command executes, after which it can be retried if
+    /// the server never completed it. Defaults to a minute.
+    pub fn lock_ttl(mut self, lock_ttl: Duration) -> Self {
+        self.lock_ttl = lock_ttl;
+        self
+    }
+}
+
+/// How often the in-memory store drops expired keys. Keys are checked for expiry when used, so
+/// this only bounds the memory held by keys that are never used again.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+struct Entry {
+    fingerprint: String,
+    /// `None` while reserved.
+    response: Option<StoredResponse>,
+    expires_at: Instant,
+}
+
+struct Entries {
+    entries: HashMap<String, Entry>,
+    swept_at: Instant,
+}
+
+impl Entries {
+    fn sweep(&mut self, now: Instant) {
+        if now.duration_since(self.swept_at) >= SWEEP_INTERVAL {
+            self.entries.retain(|_, entry| entry.expires_at > now);
+            self.swept_at = now;
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct InMemoryIdempotencyStore {
+    entries: Arc<Mutex<Entries>>,
+}
+
+impl InMemoryIdempotencyStore {
+    pub fn new() -> Self {
+        Self {
+            entries: Arc::new(Mutex::new(Entries {
+                entries: HashMap::new(),
+                swept_at: Instant::now(),
+            })),
+        }
+    }
+}
+
+impl Default for InMemoryIdempotencyStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl IdempotencyStore for InMemoryIdempotencyStore {
+    async fn reserve(
+        &self,
+        key: &str,
+        fingerprint: &str,
+        lock_ttl: Duration,
+    ) -> io::Result<Reservation> {
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+        entries.sweep(now);
+
+        Ok(match entries.entries.get(key) {
+            Some(entry) if entry.expires_at > now && entry.fingerprint != fingerprint => {
+                Reservation::Mismatched
+            }
+            Some(Entry {
+                response: Some(response),
+                expires_at,
+                ..
+            }) if *expires_at > now => Reservation::Completed(response.clone()),
+            Some(entry) if entry.expires_at > now => Reservation::InProgress,
+            _ => {
+                let entry = Entry {
+                    fingerprint: fingerprint.to_string(),
+                    response: None,
+                    expires_at: now + lock_ttl,
+                };
+                entries.entries.insert(key.to_string(), entry);
+                Reservation::Acquired
+            }
+        })
+    }
+
+    async fn complete(
+        &self,
+        key: &str,
+        fingerprint: &str,
+        response: StoredResponse,
+        ttl: Duration,
+    ) -> io::Result<()> {
+        let entry = Entry {
+            fingerprint: fingerprint.to_string(),
+            response: Some(response),
+            expires_at: Instant::now() + ttl,
+        };
+        self.entries
+            .lock()
+            .unwrap()
+            .entries
+            .insert(key.to_string(), entry);
+        Ok(())
+    }
+
+    async fn release(&self, key: &str) -> io::Result<()> {
+        self.entries.lock().unwrap().entries.remove(key);
+        Ok(())
+    }
+}
+
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteIdempotencyStore;
+
+#[cfg(feature = "sqlite")]
+mod sqlite {
+    use std::{
+        io,
+        path::Path,
+        time::{Duration, SystemTime, UNIX_EPOCH},
+    };
+
+    use axum::async_trait;
+    use rusqlite::{params, Connection, OptionalExtension};
+
+    use super::{IdempotencyStore, Reservation, StoredResponse};
+    use crate::sqlite::SharedConnection;
+
+    /// Stores responses in the `cqrs_idempotency` table, which is created if it does not exist.
+    #[derive(Clone)]
+    pub struct SqliteIdempotencyStore {
+        connection: SharedConnection,
+    }
+
+    impl SqliteIdempotencyStore {
+        pub fn open(path: impl AsRef<Path>) -> rusqlite::Result<Self> {
+            Self::new(Connection::open(path)?)
+        }
+
+        pub fn new(connection: Connection) -> rusqlite::Result<Self> {
+            connection.execute_batch(
+                "CREATE TABLE IF NOT EXISTS cqrs_idempotency (
+                    key TEXT PRIMARY KEY,
+                    status INTEGER,
+                    content_type TEXT,
+                    body BLOB,
+                    fingerprint TEXT NOT NULL,
+                    expires_at INTEGER NOT NULL
+                );
+                CREATE INDEX IF NOT EXISTS cqrs_idempotency_expires_at
+                    ON cqrs_idempotency (expires_at)",
+            )?;
+            Ok(Self {
+                connection: SharedConnection::new(connection),
+            })
+        }
+    }
+
+    #[async_trait]
+    impl IdempotencyStore for SqliteIdempotencyStore {
+        async fn reserve(
+            &self,
+            key: &str,
+            fingerprint: &str,
+            lock_ttl: Duration,
+        ) -> io::Result<Reservation> {
+            let key = key.to_string();
+            let fingerprint = fingerprint.to_string();
+            self.connection
+                .run(move |connection| {
+                    let now = millis(Duration::ZERO);
+                    let tx = connection.transaction()?;
+                    tx.execute(
+                        "DELETE FROM cqrs_idempotency WHERE expires_at <= ?1",
+                        params![now],
+                    )?;
+                    let inserted = tx.execute(
+                        "INSERT OR IGNORE INTO cqrs_idempotency (key, fingerprint, expires_at)
+                         VALUES (?1, ?2, ?3)",
+                        params![key, fingerprint, millis(lock_ttl)],
+                    )?;
+
+                    let reservation = if inserted == 1 {
+                        Reservation::Acquired
+                    } else {
+                        tx.query_row(
+                            "SELECT status, content_type, body, fingerprint FROM cqrs_idempotency
+                             WHERE key = ?1",
+                            params![key],
+                            |row| {
+                                if row.get::<_, String>(3)? != fingerprint {
+                                    return Ok(Reservation::Mismatched);
+                                }
+                                let Some(status) = row.get(0)? else {
+                                    return Ok(Reservation::InProgress);
+                                };
+                                Ok(Reservation::Completed(StoredResponse {
+                                    status,
+                                    content_type: row.get(1)?,
+                                    body: row.get(2)?,
+                                }))
+                            },
+                        )
+                        .optional()?
+                        .unwrap_or(Reservation::InProgress)
+                    };
+
+                    tx.commit()?;
+                    Ok(reservation)
+                })
+                .await
+        }
+
+        async fn complete(
+            &self,
+            key: &str,
+            fingerprint: &str,
+            response: StoredResponse,
+            ttl: Duration,
+        ) -> io::Result<()> {
+            let key = key.to_string();
+            let fingerprint = fingerprint.to_string();
+            self.connection
+                .run(move |connection| {
+                    connection.execute(
+                        "INSERT OR REPLACE INTO cqrs_idempotency
+                         (key, status, content_type, body, fingerprint, expires_at)
+                         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                        params![
+                            key,
+                            response.status,
+                            response.content_type,
+                            response.body,
+                            fingerprint,
+                            millis(ttl)
+                        ],
+                    )?;
+                    Ok(())
+                })
+                .await
+        }
+
+        async fn release(&self, key: &str) -> io::Result<()> {
+            let key = key.to_string();
+            self.connection
+                .run(move |connection| {
+                    connection
+                        .execute("DELETE FROM cqrs_idempotency WHERE key = ?1", params![key])?;
+                    Ok(())
+                })
+                .await
+        }
+    }
+
+    /// Milliseconds since the epoch, `after` from now.
+    fn millis(after: Duration) -> i64 {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        (now + after).as_millis() as i64
+    }
+}
+
+pub(crate) fn idempotent<S, B>(method_router: MethodRouter<S, B>) -> MethodRouter<S, B>
+where
+    S: Clone + Send + Sync + 'static,
+    B: HttpBody + From<Bytes> + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+{
+    method_router.layer(from_fn(honour_idempotency_key))
+}
+
+async fn honour_idempotency_key<B>(request: Request<B>, next: Next<B>) -> Response
+where
+    B: HttpBody + From<Bytes>,
+    B::Error: Into<BoxError>,
+{
+    let idempotency = request.extensions().get::<Idempotency>().cloned();
+    let key = request.headers().get(IDEMPOTENCY_KEY_HEADER).cloned();
+    let (Some(idempotency), Some(key)) = (idempotency, key) else {
+        return next.run(request).await;
+    };
+
+    let key = match key.to_str() {
+        Ok(key) if !key.is_empty() && key.len() <= 255 => {
+            format!("{} {}", request.uri().path(), key)
+        }
+        _ => {
+            return ProblemDetails::<()>::new(StatusCode::BAD_REQUEST)
+                .with_detail("The Idempotency-Key must be 1 to 255 visible ASCII characters.")
+                .into_response()
+        }
+    };
+
+    let (parts, body) = request.into_parts();
+    let body = match buffer(body).await {
+        Ok(body) => body,
+        Err(e) => return unreadable(e),
+    };
+    let fingerprint = fingerprint(&parts, &body);
+    let request = Request::from_parts(parts, B::from(body));
+
+    let store = &idempotency.store;
+    match store
+        .reserve(&key, &fingerprint, idempotency.lock_ttl)
+        .await
+    {
+        Ok(Reservation::Acquired) => {}
+        Ok(Reservation::InProgress) => {
+            return ProblemDetails::<()>::new(StatusCode::CONFLICT)
+                .with_detail("A request with the same Idempotency-Key is being processed.")
+                .into_response()
+        }
+        Ok(Reservation::Completed(response)) => return replay(response),
+        Ok(Reservation::Mismatched) => {
+            return ProblemDetails::<()>::new(StatusCode::UNPROCESSABLE_ENTITY)
+                .with_detail("The Idempotency-Key was used for a request with another input.")
+                .into_response()
+        }
+        Err(e) => return InternalError::from(Report::new(e)).into_response(),
+    }
+
+    let response = next.run(request).await;
+    if !response.status().is_success() {
+        if let Err(e) = store.release(&key).await {
+            tracing::error!("cannot release the idempotency key `{}`: {}", key, e);
+        }
+        return response;
+    }
+
+    if !fits_buffer(response.body()) {
+        tracing::warn!(
+            "the response of idempotency key `{}` is too large to be stored",
+            key
+        );
+        if let Err(e) = store.release(&key).await {
+            tracing::error!("cannot release the idempotency key `{}`: {}", key, e);
+        }
+        return response;
+    }
+
+    let (parts, body) = response.into_parts();
+    let body = match buffer(body).await {
+        Ok(body) => body,
+        Err(e) => {
+            if let Err(e) = store.release(&key).await {
+                tracing::error!("cannot release the idempotency key `{}`: {}", key, e);
+            }
+            return InternalError::from(Report::new(io::Error::other(e))).into_response();
+        }
+    };
+
+    let stored = StoredResponse {
+        status: parts.status.as_u16(),
+        content_type: parts
+            .headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .map(String::from),
+        body: body.to_vec(),
+    };
+    if let Err(e) = store
+        .complete(&key, &fingerprint, stored, idempotency.ttl)
+        .await
+    {
+        tracing::error!(
+            "cannot store the response of idempotency key `{}`: {}",
+            key,
+            e
+        );
+    }
+
+    (parts, body).into_response()
+}
+
+/// Buffers `body`, failing with an `http_body::LengthLimitError` when it is larger than
+/// `MAX_BUFFERED_BODY`.
+pub(crate) async fn buffer<B>(body: B) -> Result<Bytes, BoxError>
+where
+    B: HttpBody,
+    B::Error: Into<BoxError>,
+{
+    hyper::body::to_bytes(Limited::new(body, MAX_BUFFERED_BODY)).await
+}
+
+/// Whether `body` says it is small enough to be buffered.
+pub(crate) fn fits_buffer<B: HttpBody>(body: &B) -> bool {
+    body.size_hint()
+        .upper()
+        .is_some_and(|upper| upper <= MAX_BUFFERED_BODY as u64)
+}
+
+/// The 413 or 400 problem of a body that `buffer` could not read.
+pub(crate) fn unreadable(error: BoxError) -> Response {
+    if error.is::<LengthLimitError>() {
+        return ProblemDetails::<()>::new(StatusCode::PAYLOAD_TOO_LARGE)
+            .with_detail(format!(
+                "The body is larger than {} bytes.",
+                MAX_BUFFERED_BODY
+            ))
+            .into_response();
+    }
+
+    ProblemDetails::<()>::new(StatusCode::BAD_REQUEST)
+        .with_detail(format!("Cannot read the body: {}", error))
+        .into_response()
+}
+
+/// The FNV-1a hash of the route and the input of a request. The input is hashed as JSON when it
+/// can be decoded, so that the same input in another format or with other whitespace matches.
+/// The hash must not change between releases, as it is stored with the key.
+fn fingerprint(parts: &axum::http::request::Parts, body: &[u8]) -> String {
+    let input = Format::from_content_type(&parts.headers)
+        .and_then(|format| format.decode::<serde_json::Value>(body).ok())
+        .map(|input| input.to_string());
+    let input = input.as_ref().map_or(body, |input| input.as_bytes());
+
+    let hash = [parts.uri.path().as_bytes(), b"\n", input]
+        .into_iter()
+        .flatten()
+        .fold(0xcbf29ce484222325_u64, |hash, byte| {
+            (hash ^ u64::from(*byte)).wrapping_mul(0x100000001b3)
+        });
+    format!("{:016x}", hash)
+}
+
+fn replay(stored: StoredResponse) -> Response {
+    let mut response = stored.into_response();
+    response
+        .headers_mut()
+        .insert(IDEMPOTENT_REPLAYED_HEADER, HeaderValue::from_static("true"));
+    response
+}
+
+impl IntoResponse for StoredResponse {
+    fn into_response(self) -> Response {
+        let status = StatusCode::from_u16(self.status).unwrap_or(StatusCode::OK);
+        let mut response = (status, self.body).into_response();
+        if let Some(content_type) = self
+            .content_type
+            .and_then(|c| HeaderValue::from_str(&c).ok())
+        {
+            response
+                .headers_mut()
+                .insert(header::CONTENT_TYPE, content_type);
+        }
+        response
+    }
+}
@@ -1,39 +1,160 @@
 use axum::{
     async_trait,
-    body::HttpBody,
-    extract::{rejection::JsonRejection, FromRequest},
-    http::Request,
+    body::{Bytes, HttpBody},
+    extract::{
+        rejection::{JsonRejection, MissingJsonContentType},
+        FromRequest,
+    },
+    http::{header, Request, StatusCode},
     response::{IntoResponse, Response},
-    BoxError, Json,
+    BoxError,
 };
-use serde::de::DeserializeOwned;
+use serde::{
+    de::{DeserializeOwned, Deserializer},
+    Deserialize, Serialize,
+};
+
+use crate::{
+    format::{respond, Format},
+    problem::ProblemDetails,
+    trace::{log_input, PayloadLogging},
+    validation::InputValidator,
+};
+
+/// The error code of input that is not valid JSON or does not match the contract.
+/// Generated error codes are never negative, so it cannot clash with them.
+pub const MALFORMED_INPUT_ERROR_CODE: i64 = -1;
 
 pub struct CQRSInput<T>(pub T);
-pub struct CQRSJsonRejection(JsonRejection);
+
+pub enum CQRSRejection {
+    Json(JsonRejection),
+    /// The `Content-Type` of the input is none of the supported formats.
+    UnsupportedMediaType(String),
+    Malformed(MalformedInput),
+    Validation(Response),
+}
+
+/// Body of a malformed input rejection, in the shape of a failed `CommandResult`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "PascalCase")]
+pub struct MalformedInput {
+    pub validation_errors: Vec<InputError>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "PascalCase")]
+pub struct InputError {
+    pub property_name: String,
+    pub error_message: String,
+    pub error_code: i64,
+}
 
 #[async_trait]
 impl<T, S, B> FromRequest<S, B> for CQRSInput<T>
 where
-    T: DeserializeOwned,
+    T: DeserializeOwned + 'static,
     B: HttpBody + Send + 'static,
     B::Data: Send,
     B::Error: Into<BoxError>,
     S: Send + Sync,
 {
-    type Rejection = CQRSJsonRejection;
+    type Rejection = CQRSRejection;
 
     async fn from_request(req: Request<B>, state: &S) -> Result<Self, Self::Rejection> {
-        let inner = Json::<T>::from_request(req, state).await;
-        inner
-            .map(|Json(i)| CQRSInput(i))
-            .map_err(|c| CQRSJsonRejection(c))
+        let Some(format) = Format::from_content_type(req.headers()) else {
+            return Err(match req.headers().get(header::CONTENT_TYPE) {
+                Some(content_type) => CQRSRejection::UnsupportedMediaType(
+                    String::from_utf8_lossy(content_type.as_bytes()).into_owned(),
+                ),
+                None => CQRSRejection::Json(MissingJsonContentType::default().into()),
+            });
+        };
+
+        let validator = req.extensions().get::<InputValidator>().cloned();
+        let payload_logging = req.extensions().get::<PayloadLogging>().cloned();
+        let bytes = Bytes::from_request(req, state)
+            .await
+            .map_err(|e| CQRSRejection::Json(e.into()))?;
+        log_input(payload_logging.as_ref(), format, &bytes);
+        let input: T = deserialize(format, &bytes).map_err(CQRSRejection::Malformed)?;
+
+        match validator.and_then(|v| v.validate(&input)) {
+            Some(response) => Err(CQRSRejection::Validation(response)),
+            None => Ok(CQRSInput(input)),
+        }
     }
 }
 
-impl IntoResponse for CQRSJsonRejection {
+impl IntoResponse for CQRSRejection {
     fn into_response(self) -> Response {
-        let mut response = self.0.into_response();
-        *response.status_mut() = axum::http::StatusCode::UNPROCESSABLE_ENTITY;
-        response
+        match self {
+            CQRSRejection::Json(rejection) => {
+                let mut response = rejection.into_response();
+                *response.status_mut() = StatusCode::UNPROCESSABLE_ENTITY;
+                response
+            }
+            CQRSRejection::UnsupportedMediaType(content_type) => {
+                ProblemDetails::<()>::new(StatusCode::UNSUPPORTED_MEDIA_TYPE)
+                    .with_detail(format!("Unsupported content type `{}`", content_type))
+                    .into_response()
+            }
+            CQRSRejection::Malformed(malformed) => respond(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                &malformed,
+                Format::Json.content_type(),
+            ),
+            CQRSRejection::Validation(response) => response,
+        }
+    }
+}
+
+fn deserialize<T: DeserializeOwned>(format: Format, bytes: &[u8]) -> Result<T, MalformedInput> {
+    if format == Format::Json {
+        return serde_path_to_error::deserialize(&mut serde_json::Deserializer::from_slice(bytes))
+            .map_err(|e| {
+                let path = e.path().to_string();
+                let error = e.into_inner();
+                // the path of a syntax error is wherever the parser gave up, report it at the root
+                let syntax = error.is_syntax() || error.is_eof();
+                malformed(property_name(path, syntax), error.to_string())
+            });
+    }
+
+    match format.decode::<Tracked<T>>(bytes) {
+        Ok(Tracked(input)) => input,
+        Err(e) => Err(malformed(String::new(), e)),
+    }
+}
+
+/// Deserializes `T` with the deserializer of any format, keeping where the input does not match
+/// it instead of failing.
+struct Tracked<T>(Result<T, MalformedInput>);
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for Tracked<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let input = serde_path_to_error::deserialize(deserializer).map_err(|e| {
+            let path = e.path().to_string();
+            malformed(property_name(path, false), e.into_inner().to_string())
+        });
+        Ok(Tracked(input))
+    }
+}
+
+fn property_name(path: String, syntax: bool) -> String {
+    if path == "." || syntax {
+        String::new()
+    } else {
+        path
+    }
+}
+
+fn malformed(property_name: String, error_message: String) -> MalformedInput {
+    MalformedInput {
+        validation_errors: vec![InputError {
+            property_name,
+            error_message,
+            error_code: MALFORMED_INPUT_ERROR_CODE,
+        }],
     }
 }
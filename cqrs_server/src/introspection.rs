@@ -0,0 +1,50 @@
+use axum::{
+    body::HttpBody,
+    routing::{get, MethodRouter},
+    Json,
+};
+use serde::Serialize;
+
+use crate::{
+    handlers::RegisteredContract,
+    registry::{ContractInfo, ContractKind, ErrorCodeInfo},
+};
+
+/// A contract served by a `CQRSRouter`. `Metadata`, the properties, attributes and comments of
+/// the contract as generated, and `ErrorCodes` are only known for contracts generated with
+/// metadata. JSON Schemas are not served; `Generator::json_schemas` writes them to files.
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "PascalCase")]
+pub struct ContractDescription {
+    pub kind: ContractKind,
+    pub name: &'static str,
+    pub route: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<&'static ContractInfo>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_codes: Option<&'static [ErrorCodeInfo]>,
+}
+
+impl From<&RegisteredContract> for ContractDescription {
+    fn from(contract: &RegisteredContract) -> Self {
+        Self {
+            kind: contract.kind,
+            name: contract.name,
+            route: contract.route.clone(),
+            metadata: contract.info,
+            error_codes: contract
+                .info
+                .filter(|_| contract.kind == ContractKind::Command)
+                .map(|info| info.error_codes),
+        }
+    }
+}
+
+pub(crate) fn introspection_route<S, B>(contracts: &[RegisteredContract]) -> MethodRouter<S, B>
+where
+    S: Clone + Send + Sync + 'static,
+    B: HttpBody + Send + 'static,
+{
+    let contracts: Vec<ContractDescription> = contracts.iter().map(Into::into).collect();
+    get(move || async move { Json(contracts) })
+}
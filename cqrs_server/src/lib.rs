@@ -1,9 +1,45 @@
-#![feature(try_trait_v2)]
+#![cfg_attr(feature = "nightly", feature(try_trait_v2, try_trait_v2_residual))]
 
+pub mod aggregate;
+pub mod batch;
+pub mod cache;
 pub mod contracts;
+pub mod dispatcher;
+pub mod event_store;
+pub mod format;
 pub mod handlers;
+pub mod idempotency;
 pub mod input;
+pub mod introspection;
+pub mod metrics;
+pub mod outbox;
+pub mod problem;
+pub mod projection;
+pub mod registry;
+pub mod routing;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+#[cfg(feature = "testing")]
+pub mod testing;
+pub mod trace;
+pub mod validation;
 
+pub use aggregate::*;
+pub use batch::*;
+pub use cache::*;
 pub use contracts::*;
+pub use dispatcher::*;
+pub use event_store::*;
+pub use format::*;
 pub use handlers::*;
+pub use idempotency::*;
 pub use input::*;
+pub use introspection::*;
+pub use metrics::*;
+pub use outbox::*;
+pub use problem::*;
+pub use projection::*;
+pub use registry::*;
+pub use routing::*;
+pub use trace::*;
+pub use validation::*;
@@ -0,0 +1,275 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use axum::{
+    body::HttpBody,
+    http::{header, HeaderValue, Request, StatusCode},
+    middleware::{from_fn, Next},
+    response::{IntoResponse, Response},
+    routing::{get, MethodRouter},
+    Extension,
+};
+
+use crate::{contracts::FailedCommand, registry::ContractKind};
+
+pub const PROMETHEUS_CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/// How a request to a contract ended.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ContractOutcome {
+    Success,
+    /// A command that returned validation errors.
+    ValidationFailure,
+    /// Any other client error, e.g. malformed input, a missing content type or a query that was
+    /// not found.
+    Rejection,
+    InternalError,
+}
+
+impl ContractOutcome {
+    pub fn of(response: &Response) -> Self {
+        let status = response.status();
+        if status.is_server_error() {
+            ContractOutcome::InternalError
+        } else if response.extensions().get::<FailedCommand>().is_some() {
+            ContractOutcome::ValidationFailure
+        } else if status.is_client_error() {
+            ContractOutcome::Rejection
+        } else {
+            ContractOutcome::Success
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ContractOutcome::Success => "success",
+            ContractOutcome::ValidationFailure => "validation_failure",
+            ContractOutcome::Rejection => "rejection",
+            ContractOutcome::InternalError => "internal_error",
+        }
+    }
+}
+
+/// Latencies and outcomes of the contracts registered by `CQRSBuilder`, labelled by contract kind
+/// and name. Enable it with `router.layer(Extension(metrics))` and serve it with
+/// `CQRSBuilder::metrics`.
+///
+/// The queries of a batch are observed one by one when the router is wrapped with
+/// `BatchQueries::wrap` after this layer is added.
+#[derive(Clone)]
+pub struct Metrics {
+    buckets: Arc<Vec<f64>>,
+    contracts: Arc<Mutex<BTreeMap<(&'static str, &'static str), ContractMetrics>>>,
+}
+
+#[derive(Default)]
+struct ContractMetrics {
+    outcomes: BTreeMap<ContractOutcome, u64>,
+    error_codes: BTreeMap<String, u64>,
+    /// Observations up to each bucket, not cumulative.
+    buckets: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Upper bounds of the latency histogram buckets, in seconds. Defaults to the buckets of the
+    /// Prometheus client libraries, from 5 milliseconds to 10 seconds.
+    pub fn buckets(mut self, mut buckets: Vec<f64>) -> Self {
+        buckets.sort_by(f64::total_cmp);
+        buckets.dedup();
+        self.buckets = Arc::new(buckets);
+        self
+    }
+
+    pub fn observe(
+        &self,
+        kind: ContractKind,
+        name: &'static str,
+        seconds: f64,
+        outcome: ContractOutcome,
+        error_codes: &[serde_json::Value],
+    ) {
+        let mut contracts = self.contracts.lock().unwrap();
+        let metrics = contracts.entry((kind.as_str(), name)).or_default();
+
+        *metrics.outcomes.entry(outcome).or_default() += 1;
+        for code in error_codes {
+            *metrics
+                .error_codes
+                .entry(error_code_label(code))
+                .or_default() += 1;
+        }
+
+        metrics.buckets.resize(self.buckets.len(), 0);
+        if let Some(bucket) = self.buckets.iter().position(|le| seconds <= *le) {
+            metrics.buckets[bucket] += 1;
+        }
+        metrics.sum += seconds;
+        metrics.count += 1;
+    }
+
+    /// The metrics in the Prometheus text format.
+    pub fn render(&self) -> String {
+        let contracts = self.contracts.lock().unwrap();
+        let mut out = String::new();
+
+        out.push_str("# HELP cqrs_requests_total Requests to contracts by outcome.\n");
+        out.push_str("# TYPE cqrs_requests_total counter\n");
+        for ((kind, name), metrics) in contracts.iter() {
+            for (outcome, count) in &metrics.outcomes {
+                let labels = labels(&[
+                    ("kind", kind),
+                    ("name", name),
+                    ("outcome", outcome.as_str()),
+                ]);
+                let _ = writeln!(out, "cqrs_requests_total{} {}", labels, count);
+            }
+        }
+
+        out.push_str(
+            "# HELP cqrs_validation_errors_total Validation errors of commands by error code.\n",
+        );
+        out.push_str("# TYPE cqrs_validation_errors_total counter\n");
+        for ((kind, name), metrics) in contracts.iter() {
+            for (code, count) in &metrics.error_codes {
+                let labels = labels(&[("kind", kind), ("name", name), ("code", code)]);
+                let _ = writeln!(out, "cqrs_validation_errors_total{} {}", labels, count);
+            }
+        }
+
+        out.push_str("# HELP cqrs_request_duration_seconds Latency of requests to contracts.\n");
+        out.push_str("# TYPE cqrs_request_duration_seconds histogram\n");
+        for ((kind, name), metrics) in contracts.iter() {
+            let mut cumulative = 0;
+            for (le, count) in self.buckets.iter().zip(&metrics.buckets) {
+                cumulative += count;
+                let le = le.to_string();
+                let labels = labels(&[("kind", kind), ("name", name), ("le", &le)]);
+                let _ = writeln!(
+                    out,
+                    "cqrs_request_duration_seconds_bucket{} {}",
+                    labels, cumulative
+                );
+            }
+            let labels_inf = labels(&[("kind", kind), ("name", name), ("le", "+Inf")]);
+            let _ = writeln!(
+                out,
+                "cqrs_request_duration_seconds_bucket{} {}",
+                labels_inf, metrics.count
+            );
+
+            let labels = labels(&[("kind", kind), ("name", name)]);
+            let _ = writeln!(
+                out,
+                "cqrs_request_duration_seconds_sum{} {}",
+                labels, metrics.sum
+            );
+            let _ = writeln!(
+                out,
+                "cqrs_request_duration_seconds_count{} {}",
+                labels, metrics.count
+            );
+        }
+
+        out
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self {
+            buckets: Arc::new(vec![
+                0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+            ]),
+            contracts: Default::default(),
+        }
+    }
+}
+
+pub(crate) fn error_code_label(code: &serde_json::Value) -> String {
+    match code {
+        serde_json::Value::String(code) => code.clone(),
+        code => code.to_string(),
+    }
+}
+
+fn labels(labels: &[(&str, &str)]) -> String {
+    let labels: Vec<_> = labels
+        .iter()
+        .map(|(label, value)| {
+            let value = value
+                .replace('\\', r"\\")
+                .replace('"', r#"\""#)
+                .replace('\n', r"\n");
+            format!("{}=\"{}\"", label, value)
+        })
+        .collect();
+    format!("{{{}}}", labels.join(","))
+}
+
+pub(crate) fn instrumented<S, B>(
+    kind: ContractKind,
+    name: &'static str,
+    method_router: MethodRouter<S, B>,
+) -> MethodRouter<S, B>
+where
+    S: Clone + Send + Sync + 'static,
+    B: HttpBody + Send + 'static,
+{
+    method_router.layer(from_fn(move |request, next| {
+        measure(kind, name, request, next)
+    }))
+}
+
+async fn measure<B>(
+    kind: ContractKind,
+    name: &'static str,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let Some(metrics) = request.extensions().get::<Metrics>().cloned() else {
+        return next.run(request).await;
+    };
+
+    let start = Instant::now();
+    let response = next.run(request).await;
+    let error_codes = response
+        .extensions()
+        .get::<FailedCommand>()
+        .map_or(&[][..], |f| &f.error_codes[..]);
+    metrics.observe(
+        kind,
+        name,
+        start.elapsed().as_secs_f64(),
+        ContractOutcome::of(&response),
+        error_codes,
+    );
+    response
+}
+
+pub(crate) fn metrics_route<S, B>() -> MethodRouter<S, B>
+where
+    S: Clone + Send + Sync + 'static,
+    B: HttpBody + Send + 'static,
+{
+    get(|metrics: Option<Extension<Metrics>>| async move {
+        let Some(Extension(metrics)) = metrics else {
+            return StatusCode::NOT_FOUND.into_response();
+        };
+        let mut response = metrics.render().into_response();
+        response.headers_mut().insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static(PROMETHEUS_CONTENT_TYPE),
+        );
+        response
+    })
+}
@@ -0,0 +1,264 @@
+use std::{
+    error::Error,
+    fmt, io,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use axum::{
+    async_trait,
+    body::HttpBody,
+    extract::FromRequestParts,
+    http::{request::Parts, Request},
+    middleware::{from_fn, Next},
+    response::{IntoResponse, Response},
+    routing::MethodRouter,
+};
+use error_stack::Report;
+use serde::{Deserialize, Serialize};
+use tokio::task::JoinHandle;
+
+use crate::{contracts::FailedCommand, problem::InternalError};
+
+pub type PublishError = Box<dyn Error + Send + Sync>;
+
+/// An integration event to publish once the command that enqueued it has committed.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "PascalCase")]
+pub struct OutboxMessage {
+    pub topic: String,
+    pub payload: serde_json::Value,
+}
+
+/// A message persisted in an outbox, not delivered yet. `id` grows with every message, so that
+/// consumers can discard the duplicates of retried deliveries.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "PascalCase")]
+pub struct PendingMessage {
+    pub id: u64,
+    pub topic: String,
+    pub payload: serde_json::Value,
+}
+
+/// Persists messages until they are delivered. The event stores are outboxes too, which enqueue
+/// messages in the transaction that appends events.
+#[async_trait]
+pub trait OutboxStore: Send + Sync {
+    async fn enqueue(&self, messages: Vec<OutboxMessage>) -> io::Result<()>;
+
+    /// At most `limit` undelivered messages, oldest first.
+    async fn pending(&self, limit: usize) -> io::Result<Vec<PendingMessage>>;
+
+    async fn delivered(&self, id: u64) -> io::Result<()>;
+}
+
+/// Delivers messages to a broker, a webhook, etc.
+#[async_trait]
+pub trait Publisher: Send + Sync + 'static {
+    async fn publish(&self, message: &PendingMessage) -> Result<(), PublishError>;
+}
+
+/// The messages a command handler enqueues while it executes. They are persisted only in a unit
+/// of work with the changes of the command: pass it to `aggregate::execute_with_outbox`, or `take`
+/// the messages and enqueue them to an `OutboxStore` in the handler's own transaction.
+///
+/// Messages are dropped when the command fails, either with validation errors or any other error
+/// response. A command that succeeds with messages still enqueued is an internal error, as they
+/// would be lost: nothing persists them after the handler returned.
+///
+/// Extracting it outside of a command route of `CQRSBuilder` is an internal error too.
+#[derive(Clone, Default)]
+pub struct Outbox {
+    messages: Arc<Mutex<Vec<OutboxMessage>>>,
+}
+
+impl Outbox {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn enqueue<T: Serialize>(
+        &self,
+        topic: impl Into<String>,
+        payload: &T,
+    ) -> Result<(), serde_json::Error> {
+        let message = OutboxMessage {
+            topic: topic.into(),
+            payload: serde_json::to_value(payload)?,
+        };
+        self.messages.lock().unwrap().push(message);
+        Ok(())
+    }
+
+    /// Removes all messages enqueued so far.
+    pub fn take(&self) -> Vec<OutboxMessage> {
+        std::mem::take(&mut self.messages.lock().unwrap())
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Outbox {
+    type Rejection = InternalError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parts
+            .extensions
+            .get::<Outbox>()
+            .cloned()
+            .ok_or_else(|| Report::new(MissingOutbox).into())
+    }
+}
+
+/// An `Outbox` extracted by a handler that is not registered as a command.
+#[derive(Debug)]
+struct MissingOutbox;
+
+impl fmt::Display for MissingOutbox {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "no `Outbox` is collected on this route, register it as a command"
+        )
+    }
+}
+
+impl Error for MissingOutbox {}
+
+pub(crate) fn outboxed<S, B>(method_router: MethodRouter<S, B>) -> MethodRouter<S, B>
+where
+    S: Clone + Send + Sync + 'static,
+    B: HttpBody + Send + 'static,
+{
+    method_router.layer(from_fn(collect_outbox))
+}
+
+async fn collect_outbox<B>(mut request: Request<B>, next: Next<B>) -> Response {
+    let outbox = Outbox::new();
+    request.extensions_mut().insert(outbox.clone());
+
+    let response = next.run(request).await;
+    let messages = outbox.take();
+    let failed =
+        !response.status().is_success() || response.extensions().get::<FailedCommand>().is_some();
+    if messages.is_empty() || failed {
+        return response;
+    }
+
+    InternalError::from(Report::new(UnpersistedMessages(messages.len()))).into_response()
+}
+
+/// Messages enqueued by a command that succeeded without persisting them in its unit of work.
+#[derive(Debug)]
+struct UnpersistedMessages(usize);
+
+impl fmt::Display for UnpersistedMessages {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} outbox message(s) were not persisted, pass the `Outbox` to `execute_with_outbox`",
+            self.0
+        )
+    }
+}
+
+impl Error for UnpersistedMessages {}
+
+/// Delivers the messages of an outbox in order, at least once: a message is retried until it is
+/// published, and the ones after it wait.
+#[derive(Clone)]
+pub struct OutboxDispatcher {
+    store: Arc<dyn OutboxStore>,
+    publisher: Arc<dyn Publisher>,
+    batch_size: usize,
+    poll_interval: Duration,
+    min_backoff: Duration,
+    max_backoff: Duration,
+    /// Delivering one batch at a time, so that no message is published twice by this process.
+    lock: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl OutboxDispatcher {
+    pub fn new(store: Arc<dyn OutboxStore>, publisher: impl Publisher) -> Self {
+        Self {
+            store,
+            publisher: Arc::new(publisher),
+            batch_size: 100,
+            poll_interval: Duration::from_millis(100),
+            min_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(60),
+            lock: Default::default(),
+        }
+    }
+
+    /// How many messages are read from the store at once. Defaults to 100.
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// How often `run` looks for new messages. Defaults to 100 milliseconds.
+    pub fn poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    /// How long `run` waits after a failure, doubled with every consecutive failure up to `max`.
+    /// Defaults to 100 milliseconds up to a minute.
+    pub fn retry_backoff(mut self, min: Duration, max: Duration) -> Self {
+        self.min_backoff = min;
+        self.max_backoff = max.max(min);
+        self
+    }
+
+    /// Publishes the pending messages, up to the first failure, and returns how many were
+    /// delivered.
+    pub async fn dispatch(&self) -> Result<usize, PublishError> {
+        let _lock = self.lock.lock().await;
+        let mut delivered = 0;
+
+        loop {
+            let messages = self.store.pending(self.batch_size).await?;
+            for message in &messages {
+                self.publisher.publish(message).await?;
+                self.store.delivered(message.id).await?;
+                delivered += 1;
+            }
+
+            if messages.len() < self.batch_size {
+                return Ok(delivered);
+            }
+        }
+    }
+
+    /// Keeps dispatching until the task is aborted.
+    pub async fn run(self) {
+        let mut failures = 0;
+        loop {
+            let wait = match self.dispatch().await {
+                Ok(_) => {
+                    failures = 0;
+                    self.poll_interval
+                }
+                Err(e) => {
+                    failures += 1;
+                    let backoff = self
+                        .min_backoff
+                        .saturating_mul(1 << (failures - 1).min(16))
+                        .min(self.max_backoff);
+                    tracing::warn!(
+                        "cannot publish outbox messages, retrying in {:?}: {}",
+                        backoff,
+                        e
+                    );
+                    backoff
+                }
+            };
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Runs the dispatcher on the runtime.
+    pub fn spawn(self) -> JoinHandle<()> {
+        tokio::spawn(self.run())
+    }
+}
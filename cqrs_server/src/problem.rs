@@ -0,0 +1,164 @@
+use std::{
+    fmt,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+    body::HttpBody,
+    http::{HeaderValue, Request, StatusCode},
+    middleware::{from_fn, Next},
+    response::{IntoResponse, Response},
+    routing::MethodRouter,
+};
+use error_stack::{Context, Report};
+use serde::{Deserialize, Serialize};
+
+use crate::format::respond;
+
+pub const PROBLEM_CONTENT_TYPE: &str = "application/problem+json";
+pub const CORRELATION_ID_HEADER: &str = "x-correlation-id";
+
+/// Longer correlation ids of requests are replaced, so that they cannot flood the logs.
+const MAX_CORRELATION_ID_LENGTH: usize = 128;
+
+/// An RFC 7807 `application/problem+json` body. `error` carries a typed domain error.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ProblemDetails<E = ()> {
+    #[serde(rename = "type")]
+    pub problem_type: String,
+    pub title: String,
+    pub status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<E>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub correlation_id: Option<String>,
+}
+
+impl<E> ProblemDetails<E> {
+    pub fn new(status: StatusCode) -> Self {
+        Self {
+            problem_type: "about:blank".to_string(),
+            title: status.canonical_reason().unwrap_or_default().to_string(),
+            status: status.as_u16(),
+            detail: None,
+            error: None,
+            correlation_id: None,
+        }
+    }
+
+    pub fn with_detail(mut self, detail: impl Into<String>) -> Self {
+        self.detail = Some(detail.into());
+        self
+    }
+
+    pub fn with_error(mut self, error: E) -> Self {
+        self.error = Some(error);
+        self
+    }
+
+    pub fn with_correlation_id(mut self, correlation_id: impl Into<String>) -> Self {
+        self.correlation_id = Some(correlation_id.into());
+        self
+    }
+}
+
+impl<E> IntoResponse for ProblemDetails<E>
+where
+    E: Serialize,
+{
+    fn into_response(self) -> Response {
+        let status = StatusCode::from_u16(self.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        respond(status, &self, PROBLEM_CONTENT_TYPE)
+    }
+}
+
+/// An infrastructure failure of a handler, e.g. a database outage.
+///
+/// Handlers return a `Result` of their `CommandResult<C>` or `QueryResult<Q>` with an
+/// `InternalError`, or directly with an `error_stack::Report`, and use `?` on
+/// `error_stack::Result`s. The report is logged under a correlation id, the one of the request if
+/// it sent `x-correlation-id`, and the client gets a 500 problem with the same id and none of the
+/// report's details.
+pub struct InternalError {
+    report: Box<dyn fmt::Debug + Send + Sync>,
+}
+
+impl<C> From<Report<C>> for InternalError
+where
+    C: Context,
+{
+    fn from(report: Report<C>) -> Self {
+        Self {
+            report: Box::new(report),
+        }
+    }
+}
+
+impl fmt::Debug for InternalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.report.fmt(f)
+    }
+}
+
+impl IntoResponse for InternalError {
+    fn into_response(self) -> Response {
+        let correlation_id = REQUEST_CORRELATION_ID
+            .try_with(Clone::clone)
+            .unwrap_or_else(|_| next_correlation_id());
+        tracing::error!(correlation_id = %correlation_id, "handler failed: {:?}", self.report);
+
+        let mut response = ProblemDetails::<()>::new(StatusCode::INTERNAL_SERVER_ERROR)
+            .with_correlation_id(correlation_id.clone())
+            .into_response();
+        if let Ok(value) = HeaderValue::from_str(&correlation_id) {
+            response.headers_mut().insert(CORRELATION_ID_HEADER, value);
+        }
+        response
+    }
+}
+
+fn next_correlation_id() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_nanos() as u64);
+    let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+    format!("{:016x}-{:08x}", nanos, count as u32)
+}
+
+tokio::task_local! {
+    static REQUEST_CORRELATION_ID: String;
+}
+
+/// Sends and logs the internal errors of a route under the correlation id of their request, when
+/// it has one.
+pub(crate) fn correlated<S, B>(method_router: MethodRouter<S, B>) -> MethodRouter<S, B>
+where
+    S: Clone + Send + Sync + 'static,
+    B: HttpBody + Send + 'static,
+{
+    method_router.layer(from_fn(with_request_correlation_id))
+}
+
+async fn with_request_correlation_id<B>(request: Request<B>, next: Next<B>) -> Response {
+    let correlation_id = request
+        .headers()
+        .get(CORRELATION_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .filter(|id| !id.is_empty() && id.len() <= MAX_CORRELATION_ID_LENGTH)
+        .map(String::from);
+
+    match correlation_id {
+        Some(correlation_id) => {
+            REQUEST_CORRELATION_ID
+                .scope(correlation_id, next.run(request))
+                .await
+        }
+        None => next.run(request).await,
+    }
+}
@@ -0,0 +1,310 @@
+use std::{
+    collections::HashMap,
+    error::Error,
+    io,
+    sync::{Arc, Mutex, RwLock, RwLockReadGuard},
+    time::Duration,
+};
+
+use axum::async_trait;
+use tokio::task::JoinHandle;
+
+use crate::event_store::{EventStore, StoredEvent};
+
+pub type ProjectionError = Box<dyn Error + Send + Sync>;
+
+/// Maintains a read model from the events of all streams, in the order of the store.
+#[async_trait]
+pub trait Projection: Send + Sync + 'static {
+    /// Identifies the checkpoint of the projection.
+    fn name(&self) -> &str;
+
+    async fn apply(&self, event: &StoredEvent) -> Result<(), ProjectionError>;
+
+    /// Clears the read model, before it is rebuilt from the first event.
+    async fn reset(&self) -> Result<(), ProjectionError>;
+}
+
+/// Remembers the position of the last event each projection has applied.
+#[async_trait]
+pub trait CheckpointStore: Send + Sync {
+    /// The position of `projection`, 0 if it has not applied any event yet.
+    async fn load(&self, projection: &str) -> io::Result<u64>;
+
+    async fn save(&self, projection: &str, position: u64) -> io::Result<()>;
+}
+
+#[derive(Clone, Default)]
+pub struct InMemoryCheckpointStore {
+    positions: Arc<Mutex<HashMap<String, u64>>>,
+}
+
+impl InMemoryCheckpointStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl CheckpointStore for InMemoryCheckpointStore {
+    async fn load(&self, projection: &str) -> io::Result<u64> {
+        let positions = self.positions.lock().unwrap();
+        Ok(positions.get(projection).copied().unwrap_or_default())
+    }
+
+    async fn save(&self, projection: &str, position: u64) -> io::Result<()> {
+        let mut positions = self.positions.lock().unwrap();
+        positions.insert(projection.to_string(), position);
+        Ok(())
+    }
+}
+
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteCheckpointStore;
+
+#[cfg(feature = "sqlite")]
+mod sqlite {
+    use std::{io, path::Path};
+
+    use axum::async_trait;
+    use rusqlite::{params, Connection, OptionalExtension};
+
+    use super::CheckpointStore;
+    use crate::sqlite::SharedConnection;
+
+    /// Stores positions in the `cqrs_checkpoints` table, which is created if it does not exist.
+    #[derive(Clone)]
+    pub struct SqliteCheckpointStore {
+        connection: SharedConnection,
+    }
+
+    impl SqliteCheckpointStore {
+        pub fn open(path: impl AsRef<Path>) -> rusqlite::Result<Self> {
+            Self::new(Connection::open(path)?)
+        }
+
+        pub fn new(connection: Connection) -> rusqlite::Result<Self> {
+            connection.execute_batch(
+                "CREATE TABLE IF NOT EXISTS cqrs_checkpoints (
+                    projection TEXT PRIMARY KEY,
+                    position INTEGER NOT NULL
+                )",
+            )?;
+            Ok(Self {
+                connection: SharedConnection::new(connection),
+            })
+        }
+    }
+
+    #[async_trait]
+    impl CheckpointStore for SqliteCheckpointStore {
+        async fn load(&self, projection: &str) -> io::Result<u64> {
+            let projection = projection.to_string();
+            self.connection
+                .run(move |connection| {
+                    let position = connection
+                        .query_row(
+                            "SELECT position FROM cqrs_checkpoints WHERE projection = ?1",
+                            params![projection],
+                            |row| row.get(0),
+                        )
+                        .optional()?;
+                    Ok(position.unwrap_or_default())
+                })
+                .await
+        }
+
+        async fn save(&self, projection: &str, position: u64) -> io::Result<()> {
+            let projection = projection.to_string();
+            self.connection
+                .run(move |connection| {
+                    connection.execute(
+                        "INSERT OR REPLACE INTO cqrs_checkpoints (projection, position)
+                         VALUES (?1, ?2)",
+                        params![projection, position],
+                    )?;
+                    Ok(())
+                })
+                .await
+        }
+    }
+}
+
+/// Feeds a projection with the events of a store, from its checkpoint on.
+///
+/// Clones share the projection, so a handler can `catch_up` to read its own writes while the
+/// projector `run`s. A persistent `CheckpointStore` must only be used for persistent read models,
+/// in-memory read models have to be rebuilt on every start.
+pub struct Projector<P> {
+    store: Arc<dyn EventStore>,
+    projection: Arc<P>,
+    checkpoints: Arc<dyn CheckpointStore>,
+    batch_size: usize,
+    poll_interval: Duration,
+    /// Applying events one batch at a time, so that none is applied twice.
+    lock: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl<P: Projection> Projector<P> {
+    pub fn new(store: Arc<dyn EventStore>, projection: P) -> Self {
+        Self {
+            store,
+            projection: Arc::new(projection),
+            checkpoints: Arc::new(InMemoryCheckpointStore::new()),
+            batch_size: 1000,
+            poll_interval: Duration::from_millis(100),
+            lock: Default::default(),
+        }
+    }
+
+    /// Where the position of the projection is stored. Defaults to memory.
+    pub fn checkpoints(mut self, checkpoints: impl CheckpointStore + 'static) -> Self {
+        self.checkpoints = Arc::new(checkpoints);
+        self
+    }
+
+    /// How many events are read from the store at once. Defaults to 1000.
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// How often `run` looks for new events. Defaults to 100 milliseconds.
+    pub fn poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    pub fn projection(&self) -> &P {
+        &self.projection
+    }
+
+    /// Applies the events appended since the checkpoint and returns the new position.
+    pub async fn catch_up(&self) -> Result<u64, ProjectionError> {
+        let _lock = self.lock.lock().await;
+        let name = self.projection.name();
+        let mut position = self.checkpoints.load(name).await?;
+
+        loop {
+            let events = self.store.read_all(position, self.batch_size).await?;
+            if events.is_empty() {
+                return Ok(position);
+            }
+
+            let start = position;
+            for event in &events {
+                if let Err(e) = self.projection.apply(event).await {
+                    // keep what was applied, the failed event is retried with the next catch-up
+                    if position > start {
+                        self.checkpoints.save(name, position).await?;
+                    }
+                    return Err(e);
+                }
+                position = event.position;
+            }
+            self.checkpoints.save(name, position).await?;
+
+            if events.len() < self.batch_size {
+                return Ok(position);
+            }
+        }
+    }
+
+    /// Resets the read model and applies all events again.
+    pub async fn rebuild(&self) -> Result<u64, ProjectionError> {
+        {
+            let _lock = self.lock.lock().await;
+            self.projection.reset().await?;
+            self.checkpoints.save(self.projection.name(), 0).await?;
+        }
+        self.catch_up().await
+    }
+
+    /// Keeps catching up with the store until the task is aborted. Failures are logged and
+    /// retried with the next poll.
+    pub async fn run(self) {
+        loop {
+            if let Err(e) = self.catch_up().await {
+                tracing::error!(
+                    "cannot update the projection `{}`: {}",
+                    self.projection.name(),
+                    e
+                );
+            }
+            tokio::time::sleep(self.poll_interval).await;
+        }
+    }
+
+    /// Runs the projector on the runtime.
+    pub fn spawn(self) -> JoinHandle<()> {
+        tokio::spawn(self.run())
+    }
+}
+
+impl<P> Clone for Projector<P> {
+    fn clone(&self) -> Self {
+        Self {
+            store: self.store.clone(),
+            projection: self.projection.clone(),
+            checkpoints: self.checkpoints.clone(),
+            batch_size: self.batch_size,
+            poll_interval: self.poll_interval,
+            lock: self.lock.clone(),
+        }
+    }
+}
+
+/// State of an in-memory read model, updated one event at a time.
+pub trait View: Default + Send + Sync + 'static {
+    const NAME: &'static str;
+
+    fn apply(&mut self, event: &StoredEvent) -> Result<(), ProjectionError>;
+}
+
+/// An in-memory read model, shared between its `Projector` and the queries serving it, e.g. as
+/// state: `State(sites): State<ReadModel<Sites>>`.
+pub struct ReadModel<V> {
+    view: Arc<RwLock<V>>,
+}
+
+impl<V: View> ReadModel<V> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn read(&self) -> RwLockReadGuard<'_, V> {
+        self.view.read().unwrap()
+    }
+}
+
+impl<V: View> Default for ReadModel<V> {
+    fn default() -> Self {
+        Self {
+            view: Default::default(),
+        }
+    }
+}
+
+impl<V> Clone for ReadModel<V> {
+    fn clone(&self) -> Self {
+        Self {
+            view: self.view.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl<V: View> Projection for ReadModel<V> {
+    fn name(&self) -> &str {
+        V::NAME
+    }
+
+    async fn apply(&self, event: &StoredEvent) -> Result<(), ProjectionError> {
+        self.view.write().unwrap().apply(event)
+    }
+
+    async fn reset(&self) -> Result<(), ProjectionError> {
+        *self.view.write().unwrap() = V::default();
+        Ok(())
+    }
+}
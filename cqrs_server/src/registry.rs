@@ -0,0 +1,112 @@
+use serde::Serialize;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize)]
+pub enum ContractKind {
+    Dto,
+    Enum,
+    Query,
+    Command,
+}
+
+impl ContractKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ContractKind::Dto => "dto",
+            ContractKind::Enum => "enum",
+            ContractKind::Query => "query",
+            ContractKind::Command => "command",
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ContractInfo {
+    pub name: &'static str,
+    pub kind: ContractKind,
+    pub comment: &'static str,
+    pub attributes: &'static [AttributeInfo],
+    pub generic_parameters: &'static [&'static str],
+    pub extends: &'static [&'static str],
+    pub properties: &'static [PropertyInfo],
+    pub members: &'static [EnumMemberInfo],
+    pub error_codes: &'static [ErrorCodeInfo],
+    pub result_type: Option<&'static str>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct PropertyInfo {
+    pub name: &'static str,
+    pub type_name: &'static str,
+    pub comment: &'static str,
+    pub attributes: &'static [AttributeInfo],
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct EnumMemberInfo {
+    pub name: &'static str,
+    pub value: i64,
+    pub comment: &'static str,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ErrorCodeInfo {
+    pub name: &'static str,
+    pub code: i32,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct AttributeInfo {
+    pub name: &'static str,
+    pub arguments: &'static [AttributeArgumentInfo],
+}
+
+#[derive(Debug, Serialize)]
+pub enum AttributeArgumentInfo {
+    Positional {
+        #[serde(rename = "Position")]
+        position: i32,
+        #[serde(rename = "Value")]
+        value: ValueInfo,
+    },
+    Named {
+        #[serde(rename = "Name")]
+        name: &'static str,
+        #[serde(rename = "Value")]
+        value: ValueInfo,
+    },
+}
+
+#[derive(Debug, Serialize)]
+pub enum ValueInfo {
+    Null,
+    Number(i64),
+    FloatingPoint(f64),
+    String(&'static str),
+    Bool(bool),
+}
+
+impl ContractInfo {
+    pub fn short_name(&self) -> &'static str {
+        self.name.rsplit('.').next().unwrap()
+    }
+
+    pub fn property(&self, name: &str) -> Option<&'static PropertyInfo> {
+        self.properties.iter().find(|p| p.name == name)
+    }
+
+    pub fn attribute(&self, name: &str) -> Option<&'static AttributeInfo> {
+        self.attributes.iter().find(|a| a.name == name)
+    }
+}
+
+pub fn find_contract(
+    contracts: &'static [ContractInfo],
+    name: &str,
+) -> Option<&'static ContractInfo> {
+    contracts.iter().find(|c| c.name == name)
+}
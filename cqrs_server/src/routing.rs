@@ -0,0 +1,78 @@
+use crate::{contracts::*, registry::ContractKind};
+
+/// How the routes of commands and queries are named. The server and its clients share it, e.g.
+/// through `CQRSRouter::with_naming` and `TestClient::with_naming`, so that both ends agree.
+#[derive(Clone, Debug, Default)]
+pub struct RouteNaming {
+    prefix: String,
+    short_names: bool,
+    lowercase: bool,
+}
+
+impl RouteNaming {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The `/api/command/...` and `/api/query/...` layout used by LeanCode's .NET servers.
+    pub fn leancode() -> Self {
+        Self::new().prefix("/api")
+    }
+
+    /// Prepended to every route, e.g. `/api`. A missing leading `/` is added.
+    pub fn prefix(mut self, prefix: impl Into<String>) -> Self {
+        let prefix = prefix.into();
+        let prefix = prefix.trim_matches('/');
+        self.prefix = if prefix.is_empty() {
+            String::new()
+        } else {
+            format!("/{}", prefix)
+        };
+        self
+    }
+
+    pub fn short_names(mut self) -> Self {
+        self.short_names = true;
+        self
+    }
+
+    pub fn full_names(mut self) -> Self {
+        self.short_names = false;
+        self
+    }
+
+    pub fn lowercase(mut self) -> Self {
+        self.lowercase = true;
+        self
+    }
+
+    pub fn command<C: Command>(&self) -> String {
+        self.format(ContractKind::Command.as_str(), C::name())
+    }
+
+    pub fn query<Q: Query>(&self) -> String {
+        self.format(ContractKind::Query.as_str(), Q::name())
+    }
+
+    /// The route of a contract by its kind and name, `None` for DTOs and enums.
+    pub fn route(&self, kind: ContractKind, name: &str) -> Option<String> {
+        match kind {
+            ContractKind::Command | ContractKind::Query => Some(self.format(kind.as_str(), name)),
+            ContractKind::Dto | ContractKind::Enum => None,
+        }
+    }
+
+    fn format(&self, segment: &str, name: &str) -> String {
+        let name = if self.short_names {
+            name.rsplit('.').next().unwrap()
+        } else {
+            name
+        };
+
+        if self.lowercase {
+            format!("{}/{}/{}", self.prefix, segment, name.to_lowercase())
+        } else {
+            format!("{}/{}/{}", self.prefix, segment, name)
+        }
+    }
+}
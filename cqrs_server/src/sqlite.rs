@@ -0,0 +1,28 @@
+use std::{
+    io,
+    sync::{Arc, Mutex},
+};
+
+use rusqlite::Connection;
+
+/// A connection shared by the SQLite stores. Statements run on the blocking thread pool.
+#[derive(Clone)]
+pub(crate) struct SharedConnection(Arc<Mutex<Connection>>);
+
+impl SharedConnection {
+    pub fn new(connection: Connection) -> Self {
+        Self(Arc::new(Mutex::new(connection)))
+    }
+
+    pub async fn run<T, F>(&self, f: F) -> io::Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> rusqlite::Result<T> + Send + 'static,
+    {
+        let connection = self.0.clone();
+        tokio::task::spawn_blocking(move || f(&mut connection.lock().unwrap()))
+            .await
+            .map_err(io::Error::other)?
+            .map_err(io::Error::other)
+    }
+}
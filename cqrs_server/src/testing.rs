@@ -0,0 +1,221 @@
+use axum::{
+    body::{Body, Bytes},
+    http::{header, HeaderMap, Request, StatusCode},
+    Router,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use tower::ServiceExt;
+
+use crate::{
+    contracts::*,
+    handlers::CQRSRouter,
+    problem::{ProblemDetails, PROBLEM_CONTENT_TYPE},
+    routing::RouteNaming,
+};
+
+/// Drives a `Router` in-memory, without binding a socket.
+#[derive(Clone)]
+pub struct TestClient {
+    router: Router,
+    naming: RouteNaming,
+}
+
+#[derive(Debug)]
+pub struct TestResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Bytes,
+}
+
+impl TestClient {
+    pub fn new(router: Router) -> Self {
+        Self::with_naming(router, RouteNaming::default())
+    }
+
+    pub fn with_naming(router: Router, naming: RouteNaming) -> Self {
+        Self { router, naming }
+    }
+
+    /// Panics if the command is rejected or its response cannot be decoded.
+    pub async fn command<C>(&self, command: &C) -> CommandResult<C>
+    where
+        C: Command + Serialize + DeserializeOwned,
+    {
+        let response = self.post_json(&self.naming.command::<C>(), command).await;
+        response.json()
+    }
+
+    /// Panics if the query is rejected or its response cannot be decoded.
+    pub async fn query<Q>(&self, query: &Q) -> Q::Result
+    where
+        Q: Query + Serialize,
+    {
+        let response = self.post_json(&self.naming.query::<Q>(), query).await;
+        response.json()
+    }
+
+    /// Like `query`, but decodes not found, forbidden and failed responses instead of panicking.
+    pub async fn query_result<Q, E>(&self, query: &Q) -> QueryResult<Q, E>
+    where
+        Q: Query + Serialize,
+        E: DeserializeOwned,
+    {
+        let response = self.post_json(&self.naming.query::<Q>(), query).await;
+        response.query_result()
+    }
+
+    pub async fn post_json<T: Serialize>(&self, uri: &str, body: &T) -> TestResponse {
+        let body = serde_json::to_vec(body).expect("cannot serialize the request");
+        self.send(
+            Request::post(uri)
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body))
+                .unwrap(),
+        )
+        .await
+    }
+
+    pub async fn send(&self, request: Request<Body>) -> TestResponse {
+        let response = self
+            .router
+            .clone()
+            .oneshot(request)
+            .await
+            .expect("the router is infallible");
+        let (parts, body) = response.into_parts();
+        let body = hyper::body::to_bytes(body)
+            .await
+            .expect("cannot read the response body");
+
+        TestResponse {
+            status: parts.status,
+            headers: parts.headers,
+            body,
+        }
+    }
+}
+
+impl From<Router> for TestClient {
+    fn from(value: Router) -> Self {
+        Self::new(value)
+    }
+}
+
+impl From<CQRSRouter> for TestClient {
+    fn from(value: CQRSRouter) -> Self {
+        let naming = value.naming().clone();
+        Self::with_naming(value.into_router(), naming)
+    }
+}
+
+impl TestResponse {
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+
+    #[track_caller]
+    pub fn json<T: DeserializeOwned>(&self) -> T {
+        assert!(
+            self.status.is_success(),
+            "request failed with {}: {}",
+            self.status,
+            self.text()
+        );
+        serde_json::from_slice(&self.body)
+            .unwrap_or_else(|e| panic!("cannot decode the response ({}): {}", e, self.text()))
+    }
+
+    pub fn is_problem(&self) -> bool {
+        self.headers
+            .get(header::CONTENT_TYPE)
+            .is_some_and(|v| v == PROBLEM_CONTENT_TYPE)
+    }
+
+    #[track_caller]
+    pub fn query_result<Q, E>(&self) -> QueryResult<Q, E>
+    where
+        Q: Query,
+        E: DeserializeOwned,
+    {
+        if !self.is_problem() {
+            return QueryResult::Ok(self.json());
+        }
+
+        let problem: ProblemDetails<E> = serde_json::from_slice(&self.body)
+            .unwrap_or_else(|e| panic!("cannot decode the problem ({}): {}", e, self.text()));
+        match (self.status, problem.error) {
+            (StatusCode::NOT_FOUND, _) => QueryResult::NotFound,
+            (StatusCode::FORBIDDEN, _) => QueryResult::Forbidden,
+            (StatusCode::UNPROCESSABLE_ENTITY, Some(error)) => QueryResult::Failed(error),
+            _ => panic!("request failed with {}: {}", self.status, self.text()),
+        }
+    }
+}
+
+impl<C> CommandResult<C>
+where
+    C: Command + Serialize,
+    C::ErrorCodes: PartialEq,
+{
+    pub fn has_error_code(&self, code: &C::ErrorCodes) -> bool {
+        self.validation_errors.iter().any(|e| &e.error_code == code)
+    }
+
+    #[track_caller]
+    pub fn assert_success(&self) {
+        assert!(
+            self.was_successful(),
+            "expected the command to succeed, got {}",
+            self.describe_errors()
+        );
+    }
+
+    #[track_caller]
+    pub fn assert_error_code(&self, code: C::ErrorCodes) {
+        assert!(
+            self.has_error_code(&code),
+            "expected error code {:?}, got {}",
+            code,
+            self.describe_errors()
+        );
+    }
+
+    /// Asserts the command failed with exactly `codes`, in order.
+    #[track_caller]
+    pub fn assert_error_codes(&self, codes: &[C::ErrorCodes]) {
+        let actual: Vec<_> = self
+            .validation_errors
+            .iter()
+            .map(|e| &e.error_code)
+            .collect();
+        let expected: Vec<_> = codes.iter().collect();
+        assert_eq!(actual, expected, "unexpected error codes");
+    }
+
+    #[track_caller]
+    pub fn assert_property_error(&self, property: &str, code: C::ErrorCodes) {
+        assert!(
+            self.validation_errors
+                .iter()
+                .any(|e| e.property_name == property && e.error_code == code),
+            "expected error code {:?} on `{}`, got {}",
+            code,
+            property,
+            self.describe_errors()
+        );
+    }
+
+    fn describe_errors(&self) -> String {
+        let errors: Vec<_> = self
+            .validation_errors
+            .iter()
+            .map(|e| {
+                format!(
+                    "{:?} on `{}` ({})",
+                    e.error_code, e.property_name, e.error_message
+                )
+            })
+            .collect();
+        format!("[{}]", errors.join(", "))
+    }
+}
@@ -0,0 +1,224 @@
+use std::{io, sync::Arc, time::Instant};
+
+use axum::{
+    body::{boxed, Full, HttpBody},
+    http::{HeaderMap, Request},
+    middleware::{from_fn, Next},
+    response::{IntoResponse, Response},
+    routing::MethodRouter,
+};
+use error_stack::Report;
+use tracing::{field, Instrument, Level};
+
+use crate::{
+    contracts::{Command, FailedCommand, Query},
+    format::Format,
+    metrics::{error_code_label, ContractOutcome},
+    problem::{InternalError, CORRELATION_ID_HEADER},
+    registry::ContractKind,
+};
+
+const REDACTED: &str = "[REDACTED]";
+
+/// The id of the user sending a request, inserted into its extensions by the authentication
+/// layer, e.g. `request.extensions_mut().insert(UserId(claims.sub))`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UserId(pub String);
+
+/// Opens a `contract` span for every request to a contract registered by `CQRSBuilder`, with its
+/// `kind`, `name`, `outcome`, number of `validation_errors` and `error_codes`, and the `user_id`
+/// and `correlation_id` when they are known. Enable it with
+/// `router.layer(Extension(ContractTracing::new()))`.
+///
+/// The queries of a batch get a span each when the router is wrapped with `BatchQueries::wrap`
+/// after this layer is added. It complements rather than replaces an HTTP trace layer, which is
+/// best added last, around everything else.
+///
+/// Payloads are never logged unless a contract opts in with `log_command_payloads` or
+/// `log_query_payloads`, so that new contracts cannot leak their inputs and results into logs.
+#[derive(Clone, Default)]
+pub struct ContractTracing {
+    /// Contracts whose inputs and results are logged.
+    logged: Arc<Vec<(ContractKind, &'static str)>>,
+    redacted: Arc<Vec<String>>,
+}
+
+/// Inserted into the extensions of a request by the `contract` span when the payloads of its
+/// contract are logged.
+#[derive(Clone)]
+pub(crate) struct PayloadLogging(ContractTracing);
+
+impl ContractTracing {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Logs the inputs and results of `C` at the debug level.
+    pub fn log_command_payloads<C: Command>(self) -> Self {
+        self.log(ContractKind::Command, C::name())
+    }
+
+    /// Logs the inputs and results of `Q` at the debug level.
+    pub fn log_query_payloads<Q: Query>(self) -> Self {
+        self.log(ContractKind::Query, Q::name())
+    }
+
+    /// Hides the values of `property`, at any depth of logged payloads. Redaction is by name
+    /// only: property names are compared ignoring case, whatever contract or DTO they belong to,
+    /// and attributes of the contracts are not consulted.
+    pub fn redact(mut self, property: impl Into<String>) -> Self {
+        Arc::make_mut(&mut self.redacted).push(property.into());
+        self
+    }
+
+    fn log(mut self, kind: ContractKind, name: &'static str) -> Self {
+        let logged = Arc::make_mut(&mut self.logged);
+        if !logged.contains(&(kind, name)) {
+            logged.push((kind, name));
+        }
+        self
+    }
+
+    fn logs_payloads(&self, kind: ContractKind, name: &str) -> bool {
+        self.logged.iter().any(|&(k, n)| k == kind && n == name) && tracing::enabled!(Level::DEBUG)
+    }
+
+    fn redact_value(&self, value: &mut serde_json::Value) {
+        match value {
+            serde_json::Value::Object(object) => {
+                for (property, value) in object.iter_mut() {
+                    if self
+                        .redacted
+                        .iter()
+                        .any(|r| r.eq_ignore_ascii_case(property))
+                    {
+                        *value = REDACTED.into();
+                    } else {
+                        self.redact_value(value);
+                    }
+                }
+            }
+            serde_json::Value::Array(items) => {
+                items.iter_mut().for_each(|item| self.redact_value(item))
+            }
+            _ => {}
+        }
+    }
+
+    fn payload(&self, format: Format, bytes: &[u8]) -> String {
+        match format.decode::<serde_json::Value>(bytes) {
+            Ok(mut payload) => {
+                self.redact_value(&mut payload);
+                payload.to_string()
+            }
+            Err(e) => format!("<{}>", e),
+        }
+    }
+}
+
+/// Logs the input of the contract that is being executed, if its payloads are logged.
+pub(crate) fn log_input(logging: Option<&PayloadLogging>, format: Format, bytes: &[u8]) {
+    if let Some(PayloadLogging(config)) = logging {
+        tracing::debug!(payload = %config.payload(format, bytes), "contract input");
+    }
+}
+
+pub(crate) fn traced<S, B>(
+    kind: ContractKind,
+    name: &'static str,
+    method_router: MethodRouter<S, B>,
+) -> MethodRouter<S, B>
+where
+    S: Clone + Send + Sync + 'static,
+    B: HttpBody + Send + 'static,
+{
+    method_router.layer(from_fn(move |request, next| {
+        trace_contract(kind, name, request, next)
+    }))
+}
+
+async fn trace_contract<B>(
+    kind: ContractKind,
+    name: &'static str,
+    mut request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let Some(config) = request.extensions().get::<ContractTracing>().cloned() else {
+        return next.run(request).await;
+    };
+    let logs_payloads = config.logs_payloads(kind, name);
+    if logs_payloads {
+        request
+            .extensions_mut()
+            .insert(PayloadLogging(config.clone()));
+    }
+
+    let span = tracing::info_span!(
+        "contract",
+        kind = kind.as_str(),
+        name,
+        outcome = field::Empty,
+        validation_errors = field::Empty,
+        error_codes = field::Empty,
+        user_id = field::Empty,
+        correlation_id = field::Empty,
+    );
+    if let Some(UserId(user_id)) = request.extensions().get::<UserId>() {
+        span.record("user_id", user_id.as_str());
+    }
+    if let Some(correlation_id) = correlation_id(request.headers()) {
+        span.record("correlation_id", correlation_id);
+    }
+
+    let start = Instant::now();
+    let response = next.run(request).instrument(span.clone()).await;
+
+    let outcome = ContractOutcome::of(&response);
+    span.record("outcome", outcome.as_str());
+    if let Some(failed) = response.extensions().get::<FailedCommand>() {
+        let error_codes: Vec<_> = failed.error_codes.iter().map(error_code_label).collect();
+        span.record("validation_errors", failed.error_codes.len());
+        span.record("error_codes", error_codes.join(",").as_str());
+    }
+    // internal errors are logged under the correlation id they are sent with
+    if let Some(correlation_id) = correlation_id(response.headers()) {
+        span.record("correlation_id", correlation_id);
+    }
+
+    let elapsed_ms = start.elapsed().as_secs_f64() * 1000.0;
+    async move {
+        match outcome {
+            ContractOutcome::InternalError => {
+                tracing::warn!(elapsed_ms, "{} `{}` failed", kind.as_str(), name)
+            }
+            _ => tracing::info!(elapsed_ms, "{} `{}` executed", kind.as_str(), name),
+        }
+        if logs_payloads {
+            log_result(&config, response).await
+        } else {
+            response
+        }
+    }
+    .instrument(span)
+    .await
+}
+
+async fn log_result(config: &ContractTracing, response: Response) -> Response {
+    let Some(format) = Format::from_content_type(response.headers()) else {
+        return response;
+    };
+
+    let (parts, body) = response.into_parts();
+    let body = match hyper::body::to_bytes(body).await {
+        Ok(body) => body,
+        Err(e) => return InternalError::from(Report::new(io::Error::other(e))).into_response(),
+    };
+    tracing::debug!(payload = %config.payload(format, &body), "contract result");
+    Response::from_parts(parts, boxed(Full::from(body)))
+}
+
+fn correlation_id(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(CORRELATION_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+}
@@ -0,0 +1,368 @@
+use std::{
+    any::Any,
+    collections::HashMap,
+    fmt::Debug,
+    ops::{Bound, RangeBounds},
+    sync::Arc,
+};
+
+use axum::response::{IntoResponse, Response};
+use serde::Serialize;
+
+use crate::contracts::*;
+
+pub trait Validator<C>: Send + Sync
+where
+    C: Command,
+{
+    fn validate(&self, command: &C) -> Vec<ValidationError<C>>;
+}
+
+impl<C, F> Validator<C> for F
+where
+    C: Command,
+    F: Fn(&C) -> Vec<ValidationError<C>> + Send + Sync,
+{
+    fn validate(&self, command: &C) -> Vec<ValidationError<C>> {
+        self(command)
+    }
+}
+
+/// A single check of a value of type `T`, reporting errors of command `C` at `path`.
+pub trait Rule<C, T: ?Sized>: Send + Sync
+where
+    C: Command,
+{
+    fn check(&self, value: &T, path: &str, errors: &mut Vec<ValidationError<C>>);
+}
+
+type Check<C, T> = Box<dyn Fn(&T, &str, &mut Vec<ValidationError<C>>) + Send + Sync>;
+
+/// Rules for the properties of `T`, which is either the command itself or a value nested in it.
+pub struct Rules<C, T = C>
+where
+    C: Command,
+{
+    checks: Vec<Check<C, T>>,
+}
+
+impl<C, T> Rules<C, T>
+where
+    C: Command,
+    T: 'static,
+{
+    pub fn new() -> Self {
+        Self { checks: vec![] }
+    }
+
+    pub fn property<P, F, R>(mut self, name: &'static str, get: F, rule: R) -> Self
+    where
+        P: ?Sized,
+        F: Fn(&T) -> &P + Send + Sync + 'static,
+        R: Rule<C, P> + 'static,
+    {
+        self.checks.push(Box::new(move |value, path, errors| {
+            rule.check(get(value), &join(path, name), errors)
+        }));
+        self
+    }
+
+    pub fn validate_at(&self, value: &T, path: &str) -> Vec<ValidationError<C>> {
+        let mut errors = vec![];
+        self.check(value, path, &mut errors);
+        errors
+    }
+}
+
+impl<C, T> Default for Rules<C, T>
+where
+    C: Command,
+    T: 'static,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<C, T> Rule<C, T> for Rules<C, T>
+where
+    C: Command,
+{
+    fn check(&self, value: &T, path: &str, errors: &mut Vec<ValidationError<C>>) {
+        for check in self.checks.iter() {
+            check(value, path, errors);
+        }
+    }
+}
+
+impl<C> Validator<C> for Rules<C, C>
+where
+    C: Command,
+{
+    fn validate(&self, command: &C) -> Vec<ValidationError<C>> {
+        let mut errors = vec![];
+        self.check(command, "", &mut errors);
+        errors
+    }
+}
+
+pub trait Length {
+    fn length(&self) -> usize;
+}
+
+impl Length for str {
+    fn length(&self) -> usize {
+        self.chars().count()
+    }
+}
+
+impl Length for String {
+    fn length(&self) -> usize {
+        self.as_str().length()
+    }
+}
+
+impl<T> Length for Vec<T> {
+    fn length(&self) -> usize {
+        self.len()
+    }
+}
+
+impl<K, V> Length for HashMap<K, V> {
+    fn length(&self) -> usize {
+        self.len()
+    }
+}
+
+pub struct NotEmpty<C: Command>(C::ErrorCodes);
+pub struct MaxLength<C: Command>(usize, C::ErrorCodes);
+pub struct InRange<C: Command, T>(Bound<T>, Bound<T>, C::ErrorCodes);
+pub struct Nested<R>(R);
+pub struct Each<R>(R);
+pub struct Optional<R>(R);
+pub struct Must<C: Command, F>(F, &'static str, C::ErrorCodes);
+
+pub fn not_empty<C: Command>(code: C::ErrorCodes) -> NotEmpty<C> {
+    NotEmpty(code)
+}
+
+pub fn max_length<C: Command>(max: usize, code: C::ErrorCodes) -> MaxLength<C> {
+    MaxLength(max, code)
+}
+
+pub fn range<C, T>(range: impl RangeBounds<T>, code: C::ErrorCodes) -> InRange<C, T>
+where
+    C: Command,
+    T: Clone,
+{
+    InRange(
+        range.start_bound().cloned(),
+        range.end_bound().cloned(),
+        code,
+    )
+}
+
+/// Validates a nested DTO with its own rules, reporting errors under the property path.
+pub fn nested<R>(rules: R) -> Nested<R> {
+    Nested(rules)
+}
+
+/// Applies the rule to every item of a collection, at `Property[index]`.
+pub fn each<R>(rule: R) -> Each<R> {
+    Each(rule)
+}
+
+/// Applies the rule only when the value is present.
+pub fn optional<R>(rule: R) -> Optional<R> {
+    Optional(rule)
+}
+
+pub fn must<C: Command, F>(predicate: F, message: &'static str, code: C::ErrorCodes) -> Must<C, F> {
+    Must(predicate, message, code)
+}
+
+impl<C, T> Rule<C, T> for NotEmpty<C>
+where
+    C: Command,
+    C::ErrorCodes: Clone + Send + Sync,
+    T: Length + ?Sized,
+{
+    fn check(&self, value: &T, path: &str, errors: &mut Vec<ValidationError<C>>) {
+        if value.length() == 0 {
+            errors.push(ValidationError::new(
+                path,
+                format!("'{}' must not be empty.", path),
+                self.0.clone(),
+            ));
+        }
+    }
+}
+
+impl<C, T> Rule<C, T> for MaxLength<C>
+where
+    C: Command,
+    C::ErrorCodes: Clone + Send + Sync,
+    T: Length + ?Sized,
+{
+    fn check(&self, value: &T, path: &str, errors: &mut Vec<ValidationError<C>>) {
+        if value.length() > self.0 {
+            errors.push(ValidationError::new(
+                path,
+                format!(
+                    "The length of '{}' must be {} or fewer. You entered {}.",
+                    path,
+                    self.0,
+                    value.length()
+                ),
+                self.1.clone(),
+            ));
+        }
+    }
+}
+
+impl<C, T> Rule<C, T> for InRange<C, T>
+where
+    C: Command,
+    C::ErrorCodes: Clone + Send + Sync,
+    T: PartialOrd + Debug + Send + Sync,
+{
+    fn check(&self, value: &T, path: &str, errors: &mut Vec<ValidationError<C>>) {
+        if !(self.0.as_ref(), self.1.as_ref()).contains(value) {
+            errors.push(ValidationError::new(
+                path,
+                format!(
+                    "'{}' must be in {}. You entered {:?}.",
+                    path,
+                    describe_range(&self.0, &self.1),
+                    value
+                ),
+                self.2.clone(),
+            ));
+        }
+    }
+}
+
+impl<C, T, R> Rule<C, T> for Nested<R>
+where
+    C: Command,
+    R: Rule<C, T>,
+{
+    fn check(&self, value: &T, path: &str, errors: &mut Vec<ValidationError<C>>) {
+        self.0.check(value, path, errors)
+    }
+}
+
+impl<C, T, R> Rule<C, Vec<T>> for Each<R>
+where
+    C: Command,
+    R: Rule<C, T>,
+{
+    fn check(&self, value: &Vec<T>, path: &str, errors: &mut Vec<ValidationError<C>>) {
+        for (i, item) in value.iter().enumerate() {
+            self.0.check(item, &format!("{}[{}]", path, i), errors);
+        }
+    }
+}
+
+impl<C, T, R> Rule<C, Option<T>> for Optional<R>
+where
+    C: Command,
+    R: Rule<C, T>,
+{
+    fn check(&self, value: &Option<T>, path: &str, errors: &mut Vec<ValidationError<C>>) {
+        if let Some(value) = value {
+            self.0.check(value, path, errors);
+        }
+    }
+}
+
+impl<C, T, F> Rule<C, T> for Must<C, F>
+where
+    C: Command,
+    C::ErrorCodes: Clone + Send + Sync,
+    T: ?Sized,
+    F: Fn(&T) -> bool + Send + Sync,
+{
+    fn check(&self, value: &T, path: &str, errors: &mut Vec<ValidationError<C>>) {
+        if !(self.0)(value) {
+            errors.push(ValidationError::new(path, self.1, self.2.clone()));
+        }
+    }
+}
+
+#[cfg(feature = "regex")]
+pub struct Matches<C: Command>(regex::Regex, C::ErrorCodes);
+
+/// Panics if `pattern` is not a valid regular expression.
+#[cfg(feature = "regex")]
+pub fn regex<C: Command>(pattern: &str, code: C::ErrorCodes) -> Matches<C> {
+    Matches(regex::Regex::new(pattern).expect("invalid pattern"), code)
+}
+
+#[cfg(feature = "regex")]
+impl<C, T> Rule<C, T> for Matches<C>
+where
+    C: Command,
+    C::ErrorCodes: Clone + Send + Sync,
+    T: AsRef<str> + ?Sized,
+{
+    fn check(&self, value: &T, path: &str, errors: &mut Vec<ValidationError<C>>) {
+        if !self.0.is_match(value.as_ref()) {
+            errors.push(ValidationError::new(
+                path,
+                format!("'{}' is not in the correct format.", path),
+                self.1.clone(),
+            ));
+        }
+    }
+}
+
+/// A type-erased validator, attached to a command route and run by `CQRSInput`.
+#[derive(Clone)]
+pub(crate) struct InputValidator(Arc<ValidateInput>);
+
+type ValidateInput = dyn Fn(&dyn Any) -> Option<Response> + Send + Sync;
+
+impl InputValidator {
+    pub fn new<C, V>(validator: V) -> Self
+    where
+        C: Command + Serialize + 'static,
+        V: Validator<C> + 'static,
+    {
+        Self(Arc::new(move |input| {
+            let command = input.downcast_ref::<C>()?;
+            let errors = validator.validate(command);
+            if errors.is_empty() {
+                None
+            } else {
+                Some(CommandResult::fail(errors).into_response())
+            }
+        }))
+    }
+
+    pub fn validate(&self, input: &dyn Any) -> Option<Response> {
+        (self.0)(input)
+    }
+}
+
+fn join(path: &str, name: &str) -> String {
+    if path.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}.{}", path, name)
+    }
+}
+
+fn describe_range<T: Debug>(start: &Bound<T>, end: &Bound<T>) -> String {
+    let start = match start {
+        Bound::Included(s) => format!("[{:?}", s),
+        Bound::Excluded(s) => format!("({:?}", s),
+        Bound::Unbounded => "(-inf".to_string(),
+    };
+    let end = match end {
+        Bound::Included(e) => format!("{:?}]", e),
+        Bound::Excluded(e) => format!("{:?})", e),
+        Bound::Unbounded => "inf)".to_string(),
+    };
+    format!("{}, {}", start, end)
+}
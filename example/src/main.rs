@@ -1,10 +1,11 @@
 use std::sync::{Arc, Mutex};
 
-use axum::{extract::State, Router};
+use axum::{extract::State, Extension};
 use cqrs_server::*;
 use example::aspe_cts::tests::contracts::{
     manager::configuration::sites::{CreateSite, CreateSiteErrorCodes},
-    technician::{MyWorkFor, WorkOrderDto}, shared::AddressDto,
+    shared::AddressDto,
+    technician::{MyWorkFor, WorkOrderDto},
 };
 use tower_http::trace::{self, TraceLayer};
 use tracing::Level;
@@ -13,15 +14,26 @@ use tracing::Level;
 struct AppState(Arc<Mutex<Vec<WorkOrderDto>>>);
 
 async fn router() {
-    let app = Router::new()
-        .command(create_site)
+    let contracts = CQRSRouter::new()
+        .validated_command(create_site, site_validator())
         .query(my_work_for)
+        .metrics("/metrics")
+        .introspection("/contracts");
+    let batch = contracts.batch_queries("/batch");
+    let router = contracts
+        .into_router()
         .with_state(AppState(Arc::new(Mutex::new(Vec::new()))))
-        .layer(
-            TraceLayer::new_for_http()
-                .make_span_with(trace::DefaultMakeSpan::new().level(Level::INFO))
-                .on_response(trace::DefaultOnResponse::new().level(Level::INFO)),
-        );
+        .layer(Extension(Metrics::new()))
+        .layer(Extension(
+            ContractTracing::new()
+                .log_command_payloads::<CreateSite>()
+                .log_query_payloads::<MyWorkFor>(),
+        ));
+    let app = batch.wrap(router).layer(
+        TraceLayer::new_for_http()
+            .make_span_with(trace::DefaultMakeSpan::new().level(Level::INFO))
+            .on_response(trace::DefaultOnResponse::new().level(Level::INFO)),
+    );
 
     let addr = std::net::SocketAddr::from(([127, 0, 0, 1], 3000));
     axum::Server::bind(&addr)
@@ -34,8 +46,6 @@ async fn create_site(
     State(state): State<AppState>,
     CQRSInput(input): CQRSInput<CreateSite>,
 ) -> CommandResult<CreateSite> {
-    validate_site(&input)?;
-
     state.0.lock().unwrap().push(WorkOrderDto {
         site_id: input.name.clone(),
         order_id: input.name.clone(),
@@ -53,14 +63,18 @@ async fn create_site(
     CommandResult::success()
 }
 
-fn validate_site(input: &CreateSite) -> CommandResult<CreateSite> {
-    if input.name.is_empty() {
-        CommandResult::single_error_code(CreateSiteErrorCodes::NameIsEmpty)
-    } else if input.name.len() > 10 {
-        CommandResult::single_error_code(CreateSiteErrorCodes::NameIsTooLong)
-    } else {
-        CommandResult::success()
-    }
+fn site_validator() -> Rules<CreateSite> {
+    Rules::new()
+        .property(
+            "Name",
+            |c: &CreateSite| &c.name,
+            not_empty(CreateSiteErrorCodes::NameIsEmpty),
+        )
+        .property(
+            "Name",
+            |c: &CreateSite| &c.name,
+            max_length(10, CreateSiteErrorCodes::NameIsTooLong),
+        )
 }
 
 async fn my_work_for(